@@ -0,0 +1,26 @@
+use std::time::Instant;
+
+use factory::factory::{binary_tree, naive_efficiency};
+
+const RUNS: usize = 10;
+
+fn main() {
+    let factory = binary_tree(10);
+    let top = factory.streams["l10"].clone();
+
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        naive_efficiency(&top.borrow());
+    }
+    let naive = start.elapsed();
+
+    // throwing the caches away each run still shares the work between the levels of the tree
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        factory.invalidate_efficiencies();
+        top.borrow().efficiency();
+    }
+    let cached = start.elapsed();
+
+    println!("10-deep binary tree over {RUNS} runs: naive {naive:?}, cached {cached:?}");
+}
@@ -1,19 +1,127 @@
-use std::{cell::RefCell, cmp::Ordering, collections::HashMap, fmt::Display, rc::Rc};
-
-use crate::{lang::parser::{Expr, InfixOp, Literal}, rate::Rate, Buffer, Product, Recipe, RecipePart, Stream};
+mod analysis;
+// only the tests and anything enabling `testing` build factories this way
+#[cfg(any(test, feature = "testing"))]
+mod builder;
+mod builtins;
+mod graph;
+mod include;
+mod profile;
+mod report;
+#[cfg(feature = "serde")]
+mod snapshot;
+mod summary;
+mod validate;
+
+use std::{cell::RefCell, cmp::Ordering, collections::{HashMap, HashSet, VecDeque}, fmt::Display, path::Path, rc::Rc};
+
+pub use analysis::OptMetric;
+#[cfg(any(test, feature = "testing"))]
+pub use builder::{binary_tree, iron_gear_chain, naive_efficiency, FactoryBuilder};
+pub use graph::{GraphEdge, GraphNode, NodeKind};
+use include::Includes;
+pub use profile::{GameProfile, RoundingMode};
+#[cfg(feature = "serde")]
+pub use snapshot::FactorySnapshot;
+pub use summary::{ModelMatrix, SolutionSummary, StreamSummary};
+pub use validate::{Warning, WarningKind};
+
+use crate::{lang::{self, parser::{Expr, InfixOp, Literal}, Span}, rate::Rate, Buffer, BufferPolicy, Efficiency, EfficiencyCache, EfficiencyGeneration, EfficiencyModel, InputStreams, mult::Mult, Product, Recipe, RecipePart, Stream};
 
 pub const DEFAULT_BUF_MULT: usize = 8;
+/// Periods are written in milliseconds, so one tick is one millisecond
+pub const DEFAULT_TICKS_PER_SECOND: f64 = 1000.0;
+/// Most passes [`Factory::solve`] makes before giving up, since mults on a loop can keep raising each other
+pub const MAX_SOLVE_PASSES: usize = 64;
 
 #[derive(Clone, Debug)]
 pub struct Factory {
     pub products: HashMap<String, Rc<RefCell<Product>>>,
     pub product_names: HashMap<Product, String>,
     pub recipes: HashMap<String, Rc<RefCell<Recipe>>>,
+    /// Other recipes registered under a key in `recipes`, each taking a different set of inputs.
+    /// Calls pick whichever of these or the one in `recipes` takes the products passed to them
+    pub recipe_variants: HashMap<String, Vec<Rc<RefCell<Recipe>>>>,
     pub streams: HashMap<String, Rc<RefCell<Stream>>>,
     pub knowledge: HashMap<String, Rc<RefCell<Knowledge>>>,
     pub unresolved: Vec<String>,
     pub modules: HashMap<String, usize>,
+    /// Record buffer transfers as tick events
+    pub verbose: bool,
+    /// Tick rate, rounding and buffer sizing of the game being planned for
+    pub profile: GameProfile,
+    pub efficiency_model: EfficiencyModel,
+    /// Maximum total mult across streams whose recipe runs in each machine type
+    pub machine_budgets: HashMap<String, usize>,
+    /// Most of each raw product that sources can extract. Raw products without an entry are unlimited
+    pub raw_supply: HashMap<Product, Rate>,
+    /// Efficiency streams should reach, set by `targetEfficiency`. Streams below it are listed by [`Factory::underperforming`]
+    pub target_efficiency: Efficiency,
+    /// Targets of streams given their own with `let <name> = <stream> @ <ratio>`, which take the place of `target_efficiency`
+    pub stream_targets: HashMap<String, Efficiency>,
+    locked_modules: HashSet<String>,
+    /// Panic during `tick` if a cycle creates or destroys items in any buffer
+    pub check_conservation: bool,
     tick: usize,
+    /// Shared with every stream in the factory, so caches in a clone or another factory are never thrown away by this one
+    generation: EfficiencyGeneration,
+}
+
+/// Something shared by `Rc` alongside its copy from [`Factory::deep_clone`]
+pub type ClonePair<T> = (Rc<RefCell<T>>, Rc<RefCell<T>>);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TickEvent {
+    /// `stream` finished producing `amount` of `product` over the tick
+    Produced { stream: String, product: Product, amount: usize },
+    /// A cycle contributed `amount` progress to `knowledge`
+    Learned { knowledge: String, amount: usize, progress: Buffer },
+    /// `amount` of `product` was pulled from `from_stream`'s buffer into `to_stream`'s own buffer
+    Transfer { from_stream: String, to_stream: String, product: Product, amount: usize },
+    /// `stream`'s reserve of `product` no longer holds enough for another cycle
+    Depleted { stream: String, product: Product },
+    /// `amount` of `product` was thrown away by the policy of `stream`'s buffer for it
+    Discarded { stream: String, product: Product, amount: usize },
+    /// `stream` can't start another cycle until its full, blocking buffer of `product` is drawn down
+    Stalled { stream: String, product: Product },
+}
+
+/// State of one of a named stream's buffers after a tick
+#[derive(Clone, Debug, PartialEq)]
+pub struct TickRecord {
+    pub name: String,
+    pub product: Product,
+    /// Amount of `product` the stream made over the tick, already scaled by its mult
+    pub produced: usize,
+    pub buffer_current: usize,
+    pub buffer_max: usize,
+}
+
+/// Everything that happened over a call to [`Factory::tick_report`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct TickReport {
+    /// Tick the factory reached
+    pub tick: usize,
+    pub events: Vec<TickEvent>,
+    /// One per buffer of every named stream, sorted by stream name then product id
+    pub records: Vec<TickRecord>,
+}
+
+/// How full one of a named stream's buffers was after each tick of a [`Factory::tick_trace`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct BufferTrace {
+    pub name: String,
+    pub product: Product,
+    /// The buffer's `current` at each tick in [`TickTrace::ticks`]
+    pub samples: Vec<usize>,
+}
+
+/// Buffer history over a call to [`Factory::tick_trace`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct TickTrace {
+    /// Tick each sample was taken at, oldest first
+    pub ticks: Vec<usize>,
+    /// One per buffer of every named stream, sorted by stream name then product id
+    pub buffers: Vec<BufferTrace>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -24,7 +132,7 @@ pub enum Value {
     Knowledge(String, Rc<RefCell<Knowledge>>, usize),
     RecipePart(RecipePart),
     Call(Box<Value>, Vec<Value>),
-    MultRecipe(Box<Value>, usize),
+    MultRecipe(Box<Value>, Mult),
     Method(Box<Method>),
     Attribute(Box<Attribute>),
     Int(isize),
@@ -32,6 +140,64 @@ pub enum Value {
     String(String),
     Bool(bool),
     List(Vec<Value>),
+    Rate(Rate),
+    /// Argument passed by name to a recipe call
+    KeywordArg(String, Box<Value>),
+    /// What's left of a stream's output after part of it is routed elsewhere, from `stream - rate` or `stream - stream`
+    Tapped(Box<TappedStream>),
+    /// Function like `min` that isn't defined by any blueprint, by the name it's looked up with in [`builtins::builtin`].
+    /// Only the name is kept, since function pointers can't be compared reliably
+    Builtin(String),
+    /// Recipe argument whose link to the recipe can carry at most this rate, from `beltLimit(source, cap)`
+    Limited(Box<Value>, Rate),
+}
+
+/// A named stream with some of its output taken away
+#[derive(Clone, Debug, PartialEq)]
+pub struct TappedStream {
+    pub name: String,
+    pub source: Rc<RefCell<Stream>>,
+    /// Rate taken of each output, summed over every subtraction
+    pub taken: HashMap<Product, Rate>,
+}
+
+impl TappedStream {
+    pub fn new(name: String, source: Rc<RefCell<Stream>>) -> Self {
+        Self { name, source, taken: HashMap::new() }
+    }
+
+    /// The source's rate of `product` less what's been taken, clamped at zero.
+    /// Products the source doesn't output are `None`, the same as [`Stream::rate_of`], even if something was subtracted for them
+    pub fn rate_of(&self, product: &Product) -> Option<Rate> {
+        let rate = self.source.borrow().rate_of(product)?;
+        Some(rate - self.taken.get(product).copied().unwrap_or(Rate::ZERO))
+    }
+
+    /// Takes `rate` from the source's only output, failing if it has more than one since the rate doesn't say which
+    fn take_rate(&mut self, rate: Rate) -> Result<(), FactoryError> {
+        let outputs: Vec<Product> = self.source.borrow().recipe.borrow().outputs.iter()
+            .filter(|output| !output.voided)
+            .map(|output| *output.product.borrow())
+            .collect();
+
+        let [product] = outputs[..] else {
+            return Err(FactoryError::InvalidArguments);
+        };
+
+        *self.taken.entry(product).or_insert(Rate::ZERO) += rate;
+        Ok(())
+    }
+
+    /// Takes whatever `consumer` draws of each of the source's outputs at full efficiency
+    fn take_stream(&mut self, consumer: &Stream) {
+        for output in self.source.borrow().recipe.borrow().outputs.iter() {
+            let product = *output.product.borrow();
+
+            if let Some(inflow) = consumer.optimal_inflow_of(&product) {
+                *self.taken.entry(product).or_insert(Rate::ZERO) += inflow;
+            }
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -54,7 +220,7 @@ pub struct Attribute {
     pub name: String,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, PartialEq)]
 pub struct Knowledge {
     pub name: String,
     pub progress: Buffer,
@@ -63,6 +229,19 @@ pub struct Knowledge {
     pub unlocked: bool,
 }
 
+// recipes point back at the knowledge they grant, so only their addresses are printed
+impl std::fmt::Debug for Knowledge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Knowledge")
+            .field("name", &self.name)
+            .field("progress", &self.progress)
+            .field("recipes", &self.recipes.iter().map(Rc::as_ptr).collect::<Vec<_>>())
+            .field("dependencies", &self.dependencies.iter().map(|dep| dep.borrow().name.clone()).collect::<Vec<_>>())
+            .field("unlocked", &self.unlocked)
+            .finish()
+    }
+}
+
 impl Knowledge {
     pub fn unlockable(&self) -> bool {
         self.dependencies.iter().all(|dep| dep.borrow().unlocked)
@@ -87,6 +266,32 @@ pub enum FactoryError {
     TypeError,
     Exists(String),
     InvalidArguments,
+    UnknownWarning(String),
+    /// The module was locked with `lock_module` and can't be changed
+    ModuleLocked(String),
+    /// The product has no buffer on the stream
+    Unbuffered(String),
+    DivideByZero,
+    /// No product, recipe, stream, knowledge, method or attribute has this name
+    Undefined(String),
+    /// Streams that feed into themselves, in the order they're wired, starting and ending at the same stream
+    Cycle(Vec<String>),
+    /// Dividing or scaling a stream's mult would leave it with part of a machine, which the profile doesn't allow
+    FractionalMult,
+    /// An included file couldn't be read, lexed or parsed, with the path and the reason
+    Include(String, String),
+    /// A line passed to `eval_line` couldn't be lexed or parsed, with the reason and where in the line it went wrong
+    Syntax(String, Span),
+    /// An error from evaluating the statement at this span of source
+    At(Span, Box<FactoryError>),
+    /// An `assert` statement's condition was false, with the condition as written
+    AssertionFailed(String),
+    /// More than one variant of the recipe takes the arguments of a call, with each candidate's inputs
+    AmbiguousRecipe(String, Vec<String>),
+    /// A machine type has more streams than its `machineBudget` has machines, with the machine type and its budget
+    OverBudget(String, usize),
+    /// A raw product's `supply` cap is too small for each of its sources to run any machines, with the product
+    OverSupply(String),
 }
 
 impl Display for Value {
@@ -100,6 +305,9 @@ impl Display for Value {
                 format!("Call {{ {lhs}({}) }}", rhs)
             },
             Self::Knowledge(name, _, _) => format!("Knowledge {{ {name} }}"),
+            Self::Tapped(tapped) => format!("Tapped {{ {} }}", tapped.name),
+            Self::Builtin(name) => format!("Builtin {{ {name} }}"),
+            Self::Limited(inner, cap) => format!("Limited {{ {inner} @ {} }}", cap.to_f64()),
             e => format!("{:?}", e),
         };
 
@@ -107,8 +315,18 @@ impl Display for Value {
     }
 }
 
+impl Default for Factory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Factory {
     pub fn new() -> Self {
+        Self::with_profile(GameProfile::default())
+    }
+
+    pub fn with_profile(profile: GameProfile) -> Self {
         let mut products = HashMap::new();
         let product_names = HashMap::new();
         let recipes = HashMap::new();
@@ -124,97 +342,403 @@ impl Factory {
             products,
             product_names,
             recipes,
+            recipe_variants: HashMap::new(),
             streams,
             knowledge,
             unresolved,
             modules,
+            verbose: false,
+            profile,
+            efficiency_model: EfficiencyModel::default(),
+            machine_budgets: HashMap::new(),
+            raw_supply: HashMap::new(),
+            target_efficiency: 1.0,
+            stream_targets: HashMap::new(),
+            locked_modules: HashSet::new(),
+            check_conservation: false,
             tick: 0,
+            generation: EfficiencyGeneration::default(),
         }
     }
 
-    pub fn solve(&mut self, stream: Rc<RefCell<Stream>>) {
-        let efficiency = stream.borrow().efficiency();
-        let mut changes: Vec<(Rc<RefCell<Stream>>, usize)> = Vec::with_capacity(4);
+    /// Raises the mult of everything `stream` draws from, directly or through other streams, until each producer covers the
+    /// demand of all of its consumers. Streams are visited consumers-first, so without cycles one pass is enough.
+    /// Returns how many passes changed a mult, which is 0 if nothing needed to change
+    pub fn solve(&mut self, stream: Rc<RefCell<Stream>>) -> usize {
+        let order = consumers_first([&stream]);
+        let mut iterations = 0;
 
-        if efficiency < 1.0 {
-            // balance and solve each input
-            for input in &stream.borrow().inputs.inner {
-                for ingredient in &stream.borrow().recipe.borrow().inputs {
-                    let product = &*ingredient.product.borrow();
-                    if let Some(optimal) = stream.borrow().recipe.borrow().optimal_inflow_of(product) {
-                        let optimal = optimal * stream.borrow().mult;
+        while iterations < MAX_SOLVE_PASSES {
+            let mut changed = false;
 
-                        if let Some(rate) = input.1.borrow().recipe.borrow().optimal_outflow_of(product) {
-                            let rate = rate * input.1.borrow().mult;
+            for consumer in &order {
+                let mut inputs = consumer.borrow().inputs().inner.clone();
 
-                            if rate < optimal {
-                                let efficiency = rate / optimal;
-                                let mult = 1.0 / efficiency;
-                                let new_mult = input.1.borrow().mult as f64 * mult;
-                                changes.push((input.1.clone(), (new_mult - f64::EPSILON).ceil() as usize));
-                            }
-                        }
-                        
-                        if let Some(rate) = input.1.borrow().rate_of(product) {
-                            if rate < optimal {
-                                self.solve(input.1.clone());
-                            }
-                        }
+                // scale whatever is starving the consumer first
+                if let Some((limiting, _)) = consumer.borrow().limiting_input() {
+                    inputs.sort_by_key(|(product, _, _)| *product.borrow() != limiting);
+                }
+
+                for (product, producer, _) in inputs {
+                    let product = *product.borrow();
+                    let Some(per_machine) = producer.borrow().recipe.borrow().optimal_outflow_of(&product) else {
+                        continue;
+                    };
+
+                    // size shared producers for every consumer at once, rounding up only after summing their demand
+                    let needed = self.mult_needed(self.demand_on(&producer, &product), per_machine.normalize());
+
+                    if needed > producer.borrow().mult {
+                        producer.borrow_mut().set_mult(needed);
+                        changed = true;
                     }
                 }
             }
+
+            if !changed {
+                break;
+            }
+
+            iterations += 1;
+        }
+
+        // set_mult already bumps the generation, but solving is what most needs fresh efficiencies afterwards
+        if iterations > 0 {
+            self.invalidate_efficiencies();
+        }
+
+        iterations
+    }
+
+    /// Mult needed to make `demand` items per tick at `per_machine` items per tick each, exact when the profile allows fractional machines
+    pub fn mult_needed(&self, demand: f64, per_machine: f64) -> Mult {
+        let machines = self.machines_needed(demand, per_machine);
+
+        if self.profile.fractional_machines {
+            Mult::from_f64(machines)
+        } else {
+            Mult::from(machines.ceil() as usize)
+        }
+    }
+
+    /// Machines needed to make `demand` items per tick at `per_machine` items per tick each.
+    /// Whole numbers unless the profile allows fractional machines
+    pub fn machines_needed(&self, demand: f64, per_machine: f64) -> f64 {
+        let machines = demand / per_machine;
+
+        if self.profile.fractional_machines {
+            machines
+        } else {
+            self.profile.rounding.apply(machines)
+        }
+    }
+
+    /// Balances the whole factory by sizing every producer to the total demand of all of its consumers.
+    /// Streams are visited consumers-first, and mults are only ever raised, so running this again changes nothing.
+    /// Fails like [`Factory::apply_machine_budgets`] and [`Factory::apply_raw_supply`] if a budget or supply cap can't be met
+    pub fn solve_all(&mut self) -> Result<(), FactoryError> {
+        for producer in self.consumer_order() {
+            let mut mult = producer.borrow().mult();
+
+            for output in producer.borrow().recipe.borrow().outputs.iter().filter(|output| !output.voided) {
+                let product = *output.product.borrow();
+                let Some(per_machine) = producer.borrow().recipe.borrow().optimal_outflow_of(&product) else {
+                    continue;
+                };
+
+                let demand = self.demand_on(&producer, &product);
+                let needed = self.mult_needed(demand, per_machine.normalize());
+                mult = mult.max(needed);
+            }
+
+            if mult != producer.borrow().mult() {
+                producer.borrow_mut().set_mult(mult);
+            }
+        }
+
+        self.apply_machine_budgets()?;
+        self.apply_raw_supply()?;
+
+        Ok(())
+    }
+
+    /// Snaps every fractional mult up to whole machines, for when a factory solved with fractional machines is built in a game that can't run part of one
+    pub fn round_up_machines(&mut self) {
+        for stream in self.all_streams() {
+            if !stream.borrow().mult().is_whole() {
+                let machines = stream.borrow().machines();
+                stream.borrow_mut().set_mult(machines);
+            }
+        }
+    }
+
+    /// Shrinks source streams (streams with no inputs) so each raw product stays within its `raw_supply` cap.
+    /// Sources of the same product are scaled down together, to fractional machines if the profile allows them and whole ones otherwise.
+    /// A source can't run zero machines, so a cap too small to leave each one any is an [`FactoryError::OverSupply`] error, leaving every stream as it was
+    pub fn apply_raw_supply(&mut self) -> Result<(), FactoryError> {
+        let mut scaled = Vec::new();
+
+        for (product, cap) in &self.raw_supply {
+            let sources: Vec<Rc<RefCell<Stream>>> = self.consumer_order().into_iter()
+                .filter(|stream| stream.borrow().inputs().inner.is_empty() && stream.borrow().rate_of(product).is_some())
+                .collect();
+            let extracted: f64 = sources.iter().filter_map(|stream| stream.borrow().rate_of(product)).map(|rate| rate.normalize()).sum();
+
+            if extracted <= cap.normalize() {
+                continue;
+            }
+
+            let scale = cap.normalize() / extracted;
+
+            for source in sources {
+                let machines = source.borrow().mult().to_f64() * scale;
+                let mult = if self.profile.fractional_machines {
+                    Mult::from_f64(machines)
+                } else {
+                    Mult::from(machines.floor() as usize)
+                };
+
+                if mult.is_zero() {
+                    return Err(FactoryError::OverSupply(self.product_label(*product)));
+                }
+
+                scaled.push((source, mult));
+            }
+        }
+
+        for (source, mult) in scaled {
+            source.borrow_mut().set_mult(mult);
+        }
+
+        Ok(())
+    }
+
+    /// Raw products whose total demand is more than their `raw_supply` cap, with the shortfall in items per tick, sorted by product
+    pub fn raw_supply_shortfall(&self) -> Vec<(Product, f64)> {
+        let demand = self.total_demand();
+        let mut out: Vec<(Product, f64)> = self.raw_supply.iter().filter_map(|(product, cap)| {
+            let shortfall = demand.get(product)?.normalize() - cap.normalize();
+
+            (shortfall > 0.0).then_some((*product, shortfall))
+        }).collect();
+
+        out.sort_by_key(|(product, _)| product.id);
+        out
+    }
+
+    /// Shrinks streams in any machine type that's over its budget.
+    /// The budget counts whole machines, and is split proportionally to the machines each stream runs, with machines lost to rounding going to the streams that lost the most.
+    /// Every stream keeps at least one machine, so a machine type with more streams than its budget can't fit and is an [`FactoryError::OverBudget`] error, leaving every stream as it was
+    pub fn apply_machine_budgets(&mut self) -> Result<(), FactoryError> {
+        let budgeted: Vec<(usize, Vec<Rc<RefCell<Stream>>>)> = self.machine_budgets.iter().map(|(machine, &budget)| {
+            let streams: Vec<Rc<RefCell<Stream>>> = self.consumer_order().into_iter()
+                .filter(|stream| stream.borrow().recipe.borrow().machine.as_ref() == Some(machine))
+                .collect();
+
+            if streams.len() > budget {
+                Err(FactoryError::OverBudget(machine.to_owned(), budget))
+            } else {
+                Ok((budget, streams))
+            }
+        }).collect::<Result<_, _>>()?;
+
+        for (budget, streams) in budgeted {
+            let total: usize = streams.iter().map(|stream| stream.borrow().machines()).sum();
+
+            if total <= budget || streams.is_empty() {
+                continue;
+            }
+
+            let shares: Vec<f64> = streams.iter().map(|stream| stream.borrow().machines() as f64 * budget as f64 / total as f64).collect();
+            let mut mults: Vec<usize> = shares.iter().map(|share| (share.floor() as usize).max(1)).collect();
+
+            // streams bumped up to one machine can push the total over, so take machines back from whoever is furthest over their share
+            while mults.iter().sum::<usize>() > budget {
+                let idx = (0..mults.len())
+                    .filter(|&idx| mults[idx] > 1)
+                    .max_by(|&lhs, &rhs| (mults[lhs] as f64 - shares[lhs]).total_cmp(&(mults[rhs] as f64 - shares[rhs])))
+                    .expect("there are no more streams than machines in the budget");
+
+                mults[idx] -= 1;
+            }
+
+            let mut order: Vec<usize> = (0..streams.len()).collect();
+            order.sort_by(|&lhs, &rhs| (shares[rhs] - shares[rhs].floor()).total_cmp(&(shares[lhs] - shares[lhs].floor())));
+
+            for idx in order {
+                if mults.iter().sum::<usize>() >= budget {
+                    break;
+                }
+
+                mults[idx] += 1;
+            }
+
+            for (stream, mult) in streams.iter().zip(mults) {
+                stream.borrow_mut().set_mult(mult);
+            }
         }
 
-        for (stream, mult) in changes {
-            let old_mult = stream.borrow().mult;
-            let mult_mult = mult / old_mult;
+        Ok(())
+    }
+
+    /// Items per tick of `product` that consumers of `producer` want from it at full efficiency.
+    /// A consumer with several sources for the product splits its demand between them evenly
+    pub fn demand_on(&self, producer: &Rc<RefCell<Stream>>, product: &Product) -> f64 {
+        self.consumers_of(producer, product).iter().filter_map(|consumer| {
+            let consumer = consumer.borrow();
+            let sources = consumer.inputs().inner.iter().filter(|(input, _, _)| &*input.borrow() == product).count();
+
+            consumer.optimal_inflow_of(product).map(|rate| rate.normalize() / sources as f64)
+        }).sum()
+    }
+
+    /// Items per tick of `product` that `producer` makes but its consumers don't draw at their current efficiency
+    pub fn surplus_of(&self, producer: &Rc<RefCell<Stream>>, product: &Product) -> f64 {
+        let Some(produced) = producer.borrow().rate_of(product) else {
+            return 0.0;
+        };
+
+        let drawn: f64 = self.consumers_of(producer, product).iter().filter_map(|consumer| {
+            let consumer = consumer.borrow();
+            let sources = consumer.inputs().inner.iter().filter(|(input, _, _)| &*input.borrow() == product).count();
+
+            consumer.optimal_inflow_of(product).map(|rate| rate.normalize() * self.efficiency_of(&consumer) / sources as f64)
+        }).sum();
+
+        (produced.normalize() - drawn).max(0.0)
+    }
+
+    /// Errors with the streams on a loop if any stream takes input, directly or through others, from itself.
+    /// A recipe consuming the product it makes is fine as long as another stream supplies it
+    pub fn validate_acyclic(&self) -> Result<(), FactoryError> {
+        fn visit(stream: &Rc<RefCell<Stream>>, path: &mut Vec<Rc<RefCell<Stream>>>, done: &mut Vec<Rc<RefCell<Stream>>>) -> Option<Vec<Rc<RefCell<Stream>>>> {
+            if let Some(start) = path.iter().position(|seen| Rc::ptr_eq(seen, stream)) {
+                let mut cycle = path[start..].to_vec();
+                cycle.push(stream.clone());
+                return Some(cycle);
+            }
+
+            if done.iter().any(|seen| Rc::ptr_eq(seen, stream)) {
+                return None;
+            }
+
+            path.push(stream.clone());
 
-            for (_, buf) in stream.borrow_mut().buffers.iter_mut() {
-                buf.max *= mult_mult;
+            for (_, input, _) in &stream.borrow().inputs().inner {
+                if let Some(cycle) = visit(input, path, done) {
+                    return Some(cycle);
+                }
             }
 
-            stream.borrow_mut().mult = mult;
+            path.pop();
+            done.push(stream.clone());
+            None
+        }
+
+        let mut names: Vec<&String> = self.streams.keys().collect();
+        names.sort();
 
-            self.solve(stream.clone());
+        let mut done = Vec::new();
+
+        for name in names {
+            if let Some(cycle) = visit(&self.streams[name], &mut Vec::new(), &mut done) {
+                // walked from consumers to inputs, so flip it to follow the items
+                return Err(FactoryError::Cycle(cycle.iter().rev().map(|stream| self.stream_name(stream)).collect()));
+            }
         }
+
+        Ok(())
+    }
+
+    /// Every stream ordered so that each one comes before all of the streams it takes inputs from
+    fn consumer_order(&self) -> Vec<Rc<RefCell<Stream>>> {
+        let mut names: Vec<&String> = self.streams.keys().collect();
+        names.sort();
+
+        consumers_first(names.into_iter().map(|name| &self.streams[name]))
+    }
+
+    pub fn add_mod(&mut self, ast: Vec<Expr>) -> Result<(), FactoryError> {
+        self.add_module("base", ast)
     }
 
-    pub fn add_mod(&mut self, mut ast: Vec<Expr>) -> Result<(), FactoryError> {
+    /// Adds a mod under its own module, so its products and recipes don't collide with same-named ones from other mods.
+    /// Loads in two phases, declaring every product and recipe name before resolving any body, so definitions can refer to names further down
+    pub fn add_module(&mut self, module: &str, ast: Vec<Expr>) -> Result<(), FactoryError> {
+        let mut ast = Self::expand_includes(ast, Path::new("."), &mut Includes::default())?;
+
+        self.declare(&ast, module)?;
+        self.check_defined(&ast, module)?;
+
+        // products are done after declaring, and recipes have to be resolved before any stream runs them
+        ast.retain(|expr| !matches!(expr, Expr::Product { .. }));
         ast.sort_by(|lhs, rhs| {
             match (lhs, rhs) {
-                (Expr::Product { .. }, Expr::Product { .. }) => Ordering::Equal,
-                (Expr::Product { .. }, _) => Ordering::Less,
-                (_, Expr::Product { .. }) => Ordering::Greater,
                 (Expr::Knowledge { .. }, Expr::Knowledge { .. }) => Ordering::Equal,
                 (Expr::Knowledge { .. }, _) => Ordering::Less,
                 (_, Expr::Knowledge { .. }) => Ordering::Greater,
+                (Expr::Recipe { .. }, Expr::Recipe { .. }) => Ordering::Equal,
+                (Expr::Recipe { .. }, _) => Ordering::Less,
+                (_, Expr::Recipe { .. }) => Ordering::Greater,
                 (_, _) => Ordering::Equal,
             }
         });
         
         for expr in ast {
-            self.process_expr(expr, "base")?;
+            self.process_expr(expr, module)?;
         }
 
         Ok(())
     }
 
+    /// Loads a factory file. Any products and recipes it defines are its own, under the `factory` module, so they're registered
+    /// as `__FACTORY::name` and never collide with the base mod's. Names used in the file, and by methods like
+    /// [`Factory::add_stream`] that act on the factory, find its own first and fall back to base
     pub fn add_factory(&mut self, ast: Vec<Expr>) -> Result<(), FactoryError> {
+        self.add_module("factory", ast)
+    }
+
+    /// First phase of loading: registers every product in `ast`, and a placeholder for every recipe that the second phase fills in
+    fn declare(&mut self, ast: &[Expr], module: &str) -> Result<(), FactoryError> {
         for expr in ast {
-            self.process_user_expr(expr)?;
+            match expr {
+                Expr::Product { name } => self.register_product(name, module)?,
+                Expr::Recipe { name, .. } => self.declare_recipe(name, module)?,
+                _ => {},
+            }
         }
 
         Ok(())
     }
 
-    pub fn process_user_expr(&mut self, expr: Expr) -> Result<(), FactoryError> {
+    /// Runs a single line of blueprint as if it were part of the factory file, returning the value of its last statement.
+    /// The trailing `;` may be left off. Lines that don't lex or parse leave the factory untouched
+    pub fn eval_line(&mut self, line: &str) -> Result<Option<Value>, FactoryError> {
+        let line = line.trim();
+        let line = if line.ends_with(';') || line.ends_with('}') { line.to_owned() } else { format!("{line};") };
+
+        let tokens = lang::tokenize(&line).map_err(|err| FactoryError::Syntax(err.to_string(), err.span()))?;
+        let ast = lang::parse_spanned(tokens).map_err(|err| FactoryError::Syntax(err.to_string(), err.span()))?;
+        let mut includes = Includes::default();
+        let mut out = None;
+
+        for (expr, span) in ast {
+            let at = |err| FactoryError::At(span, Box::new(err));
+
+            for expr in Self::expand_includes(vec![expr], Path::new("."), &mut includes).map_err(at)? {
+                out = self.process_user_expr(expr).map_err(at)?;
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn process_user_expr(&mut self, expr: Expr) -> Result<Option<Value>, FactoryError> {
         match expr {
             Expr::Product { .. }
-            | Expr::Recipe { .. } => {},
-            _ => { self.process_expr(expr, "factory")?; }
+            | Expr::Recipe { .. } => Ok(None),
+            _ => self.process_expr(expr, "factory"),
         }
-
-        Ok(())
     }
 
     fn process_expr(&mut self, expr: Expr, module: &str) -> Result<Option<Value>, FactoryError> {
@@ -223,36 +747,60 @@ impl Factory {
                 self.register_product(&name, module)?;
                 Ok(None)
             },
-            Expr::Recipe { name, inputs, outputs, period } => {
-                self.register_recipe(&name, inputs, outputs, *period, module)?;
+            Expr::Recipe { name, inputs, outputs, period, power } => {
+                self.register_recipe(&name, inputs, outputs, *period, power.map(|power| *power), module)?;
                 Ok(None)
             },
-            Expr::Assign { name, rhs } => {
-                self.register_stream(&name, *rhs, module)?;
-                Ok(None)
-            }
+            Expr::Assign { name, rhs } => match *rhs {
+                Expr::Objective { inner, target } => {
+                    self.register_stream(&name, *inner, module)?;
+                    self.stream_targets.insert(Self::qualified_stream_name(module, &name), target);
+                    Ok(None)
+                },
+                rhs => {
+                    self.register_stream(&name, rhs, module)?;
+                    Ok(None)
+                },
+            },
             Expr::Ident(ident) => {
-                if let Some(stream) = self.streams.get(&ident) {
+                let qualified = Self::qualified_stream_name(module, &ident);
+
+                // a module's own streams shadow the factory's
+                if let Some(stream) = self.streams.get(&qualified) {
+                    Ok(Some(Value::Stream(qualified, stream.clone())))
+                } else if let Some(stream) = self.streams.get(&ident) {
                     Ok(Some(Value::Stream(ident, stream.clone())))
-                } else if let Some(recipe) = self.recipes.get(&ident) {
+                } else if let Some(recipe) = self.find_recipe(module, &ident) {
                     Ok(Some(Value::Recipe(ident, recipe.clone())))
-                } else if let Some(product) = self.products.get(&ident) {
+                } else if let Some(product) = self.find_product(module, &ident) {
                     Ok(Some(Value::Product(ident, product.clone())))
                 } else if let Some(knowledge) = self.knowledge.get(&ident) {
                     Ok(Some(Value::Knowledge(ident, knowledge.clone(), 1)))  
                 } else {
-                    panic!("Unknown identifier: {ident}");
+                    Err(FactoryError::Undefined(ident))
                 }
             },
             Expr::Call { lhs, args } => {
-                let lhs = self.process_expr(*lhs, module)?.unwrap();
+                // names the blueprint hasn't defined can still be builtins like `min`
+                let lhs = match self.process_expr(*lhs, module) {
+                    Err(FactoryError::Undefined(name)) if builtins::builtin(&name).is_some() => Value::Builtin(name),
+                    lhs => lhs?.ok_or(FactoryError::UnexpectedEof)?,
+                };
                 let mut args_out = Vec::with_capacity(args.len());
 
                 for expr in args {
-                    if let Some(value) = self.process_expr(expr, module)? {
-                        args_out.push(value);
-                    } else {
-                        return Err(FactoryError::UnexpectedEof)
+                    let (name, expr) = match expr {
+                        Expr::KeywordArg { name, value } => (Some(name), *value),
+                        expr => (None, expr),
+                    };
+
+                    let Some(value) = self.process_expr(expr, module)? else {
+                        return Err(FactoryError::UnexpectedEof);
+                    };
+
+                    match name {
+                        Some(name) => args_out.push(Value::KeywordArg(name, Box::new(value))),
+                        None => args_out.push(value),
                     }
                 }
 
@@ -263,15 +811,18 @@ impl Factory {
                     Value::Recipe(..) => {
                         Ok(Some(Value::Call(Box::new(lhs), args_out)))
                     },
-                    _ => unimplemented!()
+                    Value::Builtin(name) => {
+                        let builtin = builtins::builtin(&name).ok_or(FactoryError::Undefined(name))?;
+                        builtin(args_out).map(Some)
+                    },
+                    _ => Err(FactoryError::TypeError)
                 }
             }
             Expr::InfixOp { lhs, op, rhs } => {
-                // unwrapping is fine here cause only expressions that return Some from this method should be put in an InfixOp
-                let lhs = self.process_expr(*lhs, module)?.unwrap();
-                let rhs = self.process_expr(*rhs, module)?.unwrap();
+                let lhs = self.process_expr(*lhs, module)?.ok_or(FactoryError::UnexpectedEof)?;
+                let rhs = self.process_expr(*rhs, module)?.ok_or(FactoryError::UnexpectedEof)?;
 
-                Ok(Some(self.process_op(lhs, op, rhs)))
+                Ok(Some(self.process_op(lhs, op, rhs)?))
             },
             Expr::Literal(literal) => {
                 Ok(Some(match literal {
@@ -279,18 +830,78 @@ impl Factory {
                     Literal::Float(e)  => Value::Float(e),
                     Literal::String(e) => Value::String(e),
                     Literal::Bool(e) => Value::Bool(e),
+                    Literal::Rate(amount, unit) => Value::Rate(Rate::from_f64_ticks(amount as usize, unit.to_ticks(self.profile.ticks_per_second))),
+                    // plain integers are already ticks, seconds are rounded to the nearest tick
+                    Literal::Seconds(e) => Value::Int((e * self.profile.ticks_per_second).round() as isize),
                 }))
             },
             Expr::Access { lhs, rhs } => {
-                let lhs = self.process_expr(*lhs, module)?.unwrap();
+                let lhs = match self.process_expr(*lhs, module)?.ok_or(FactoryError::UnexpectedEof)? {
+                    Value::Attribute(attribute) => self.attribute_value(*attribute)?,
+                    // nested streams like `Iron().efficiency()` are built so they have methods too
+                    lhs @ Value::Call(..) => Value::Stream("<unnamed>".to_owned(), self.parse_call(lhs)?),
+                    Value::MultRecipe(call, mult) => {
+                        Value::Stream("<unnamed>".to_owned(), self.parse_call(*call).inspect(|stream| stream.borrow_mut().set_mult(mult))?)
+                    },
+                    lhs => lhs,
+                };
 
-                Ok(Some(lhs.access(&rhs)))
+                Ok(Some(lhs.access(&rhs)?))
             },
             Expr::Knowledge { name, outputs } => {
                 self.register_knowledge(&name, outputs, module)?;
 
                 Ok(None)
             },
+            Expr::MachineBudget { machine, budget } => {
+                let budget = self.usize_from_expr(*budget, module)?;
+                self.machine_budgets.insert(machine, budget);
+
+                Ok(None)
+            },
+            Expr::Supply { product, rate } => {
+                let product = *self.find_product(module, &product).ok_or(FactoryError::InvalidArguments)?.borrow();
+
+                match self.process_expr(*rate, module)? {
+                    Some(Value::Rate(rate)) => {
+                        self.raw_supply.insert(product, rate);
+                        Ok(None)
+                    },
+                    Some(_) => Err(FactoryError::TypeError),
+                    None => Err(FactoryError::UnexpectedEof),
+                }
+            },
+            Expr::Source { product, rate } => {
+                let name = format!("<source {product}>");
+                Ok(Some(Value::Stream(name, self.source_stream(&product, *rate, module)?)))
+            },
+            // spliced in by add_mod and add_factory before anything is processed
+            Expr::Include(path) => Err(FactoryError::Include(path, "includes can only be used at the top level".to_owned())),
+            // only meaningful as a call argument, which is handled by the call
+            Expr::KeywordArg { .. } => Err(FactoryError::TypeError),
+            Expr::TargetEfficiency { ratio } => {
+                self.target_efficiency = ratio;
+                Ok(None)
+            },
+            // only meaningful as the value of a `let`, which handles it
+            Expr::Objective { .. } => Err(FactoryError::TypeError),
+            Expr::Assert(condition) => {
+                let text = condition.to_string();
+
+                match self.process_expr(*condition, module)?.ok_or(FactoryError::UnexpectedEof)? {
+                    Value::Bool(true) => Ok(None),
+                    Value::Bool(false) => Err(FactoryError::AssertionFailed(text)),
+                    _ => Err(FactoryError::TypeError),
+                }
+            },
+            Expr::Void { inner } => {
+                match self.process_expr(*inner, module)? {
+                    Some(Value::RecipePart(part)) => Ok(Some(Value::RecipePart(RecipePart { voided: true, ..part }))),
+                    Some(Value::Product(_, product)) => Ok(Some(Value::RecipePart(RecipePart { product, amount: 1, voided: true }))),
+                    Some(_) => Err(FactoryError::TypeError),
+                    None => Err(FactoryError::UnexpectedEof),
+                }
+            },
             Expr::List { contents } => {
                 let mut exprs = Vec::with_capacity(contents.len());
                 
@@ -302,35 +913,119 @@ impl Factory {
 
                 Ok(Some(Value::List(exprs)))
             }
-            _ => todo!("{:?}", expr),
+            _ => Err(FactoryError::TypeError),
+        }
+    }
+
+    /// Multiplies the mult of a recipe call, like `Iron() * 3 / 2`.
+    /// Mults count whole machines unless the profile allows fractional ones, so anything else has nothing to round to
+    fn scale_call(&self, call: Value, by: Mult) -> Result<Value, FactoryError> {
+        let (call, mult) = match call {
+            Value::MultRecipe(call, mult) => (call, mult * by),
+            call => (Box::new(call), by),
+        };
+
+        if mult.is_zero() {
+            Err(FactoryError::InvalidArguments)
+        } else if mult.is_whole() || self.profile.fractional_machines {
+            Ok(Value::MultRecipe(call, mult))
+        } else {
+            Err(FactoryError::FractionalMult)
         }
     }
 
-    fn process_op(&self, lhs: Value, op: InfixOp, rhs: Value) -> Value {
+    fn process_op(&self, lhs: Value, op: InfixOp, rhs: Value) -> Result<Value, FactoryError> {
         match (lhs.clone(), op, rhs.clone()) {
             (Value::Product(_, product), InfixOp::Mul, Value::Int(amount))
             | (Value::Int(amount), InfixOp::Mul, Value::Product(_, product)) => {
-                Value::RecipePart(RecipePart { product, amount: amount as usize })
+                Ok(Value::RecipePart(RecipePart { product, amount: amount as usize, voided: false }))
             },
-            (Value::Call(..), InfixOp::Mul, Value::Int(mult))
-            | (Value::Int(mult), InfixOp::Mul, Value::Call(..)) => {
-                Value::MultRecipe(Box::new(lhs), mult as usize)
+            (Value::Call(..), InfixOp::Mul, Value::Int(mult)) if mult > 0 => {
+                Ok(Value::MultRecipe(Box::new(lhs), Mult::from(mult as usize)))
+            },
+            (Value::Int(mult), InfixOp::Mul, Value::Call(..)) if mult > 0 => {
+                Ok(Value::MultRecipe(Box::new(rhs), Mult::from(mult as usize)))
             },
             (Value::MultRecipe(recipe, mult), InfixOp::Mul, Value::Int(mult2))
-            | (Value::Int(mult2), InfixOp::Mul, Value::MultRecipe(recipe, mult)) => {
-                Value::MultRecipe(recipe, mult * mult2 as usize)
+            | (Value::Int(mult2), InfixOp::Mul, Value::MultRecipe(recipe, mult)) if mult2 > 0 => {
+                Ok(Value::MultRecipe(recipe, mult * Mult::from(mult2 as usize)))
+            },
+            // a stream with no machines has nothing to size its buffers by
+            (Value::Call(..) | Value::MultRecipe(..), InfixOp::Mul, Value::Int(_))
+            | (Value::Int(_), InfixOp::Mul, Value::Call(..) | Value::MultRecipe(..)) => Err(FactoryError::InvalidArguments),
+            (Value::Call(..) | Value::MultRecipe(..), InfixOp::Mul, Value::Float(scale)) if scale > 0.0 => self.scale_call(lhs, Mult::from_f64(scale)),
+            (Value::Float(scale), InfixOp::Mul, Value::Call(..) | Value::MultRecipe(..)) if scale > 0.0 => self.scale_call(rhs, Mult::from_f64(scale)),
+            (Value::Int(lhs), InfixOp::Mul, Value::Int(rhs)) => Ok(Value::Int(lhs * rhs)),
+            (Value::Call(..) | Value::MultRecipe(..) | Value::Int(_), InfixOp::Div, Value::Int(0)) => Err(FactoryError::DivideByZero),
+            (Value::Call(..), InfixOp::Div, Value::Int(1)) => Ok(lhs),
+            (Value::Call(..) | Value::MultRecipe(..), InfixOp::Div, Value::Int(div)) if div > 0 => self.scale_call(lhs, Mult::new(1, div as usize)),
+            (Value::Call(..) | Value::MultRecipe(..), InfixOp::Div, Value::Int(_)) => Err(FactoryError::FractionalMult),
+            (Value::Int(lhs), InfixOp::Div, Value::Int(rhs)) => Ok(Value::Int(lhs / rhs)),
+            (Value::Int(lhs), op, Value::Int(rhs)) if op.is_comparison() => Ok(Value::Bool(op.compare(lhs.partial_cmp(&rhs)))),
+            (Value::Rate(lhs), op, Value::Rate(rhs)) if op.is_comparison() => Ok(Value::Bool(op.compare(Some(lhs.cmp(&rhs))))),
+            // mixed comparisons happen as floats
+            (Value::Int(_) | Value::Float(_), op, Value::Int(_) | Value::Float(_)) if op.is_comparison() => {
+                let (lhs, rhs) = match (lhs, rhs) {
+                    (Value::Int(lhs), Value::Float(rhs)) => (lhs as f64, rhs),
+                    (Value::Float(lhs), Value::Int(rhs)) => (lhs, rhs as f64),
+                    (Value::Float(lhs), Value::Float(rhs)) => (lhs, rhs),
+                    _ => return Err(FactoryError::TypeError),
+                };
+
+                Ok(Value::Bool(op.compare(lhs.partial_cmp(&rhs))))
             },
-            (Value::Int(lhs), InfixOp::Mul, Value::Int(rhs)) => Value::Int(lhs * rhs),
             (Value::Knowledge(name, knowledge, old_mult), InfixOp::Mul, Value::Int(mult))
             | (Value::Int(mult), InfixOp::Mul, Value::Knowledge(name, knowledge, old_mult)) => {
-                Value::Knowledge(name, knowledge, old_mult * mult as usize)
+                Ok(Value::Knowledge(name, knowledge, old_mult * mult as usize))
+            },
+            (Value::Stream(name, stream), InfixOp::Sub, Value::Rate(rate)) => {
+                let mut tapped = TappedStream::new(name, stream);
+                tapped.take_rate(rate)?;
+                Ok(Value::Tapped(Box::new(tapped)))
+            },
+            (Value::Tapped(mut tapped), InfixOp::Sub, Value::Rate(rate)) => {
+                tapped.take_rate(rate)?;
+                Ok(Value::Tapped(tapped))
+            },
+            (Value::Stream(name, stream), InfixOp::Sub, Value::Stream(_, consumer)) => {
+                let mut tapped = TappedStream::new(name, stream);
+                tapped.take_stream(&consumer.borrow());
+                Ok(Value::Tapped(Box::new(tapped)))
+            },
+            (Value::Tapped(mut tapped), InfixOp::Sub, Value::Stream(_, consumer)) => {
+                tapped.take_stream(&consumer.borrow());
+                Ok(Value::Tapped(tapped))
             },
             (Value::Attribute(attribute), InfixOp::Assign, _) => {
                 match (attribute.object, rhs) {
                     (Value::Knowledge(_, knowledge, _), Value::Int(rhs)) => {
                         match attribute.name.as_str() {
                             "threshold" => knowledge.borrow_mut().progress.max = rhs as usize,
-                            _ => unimplemented!()
+                            _ => return Err(FactoryError::Undefined(attribute.name)),
+                        }
+                    },
+                    (Value::Recipe(_, recipe), Value::Int(rhs)) => {
+                        match attribute.name.as_str() {
+                            "buildings" => recipe.borrow_mut().buildings = rhs as usize,
+                            _ => return Err(FactoryError::Undefined(attribute.name)),
+                        }
+                    },
+                    (Value::Recipe(_, recipe), Value::String(rhs)) => {
+                        match attribute.name.as_str() {
+                            "machine" => recipe.borrow_mut().machine = Some(rhs),
+                            _ => return Err(FactoryError::Undefined(attribute.name)),
+                        }
+                    },
+                    (Value::Recipe(_, recipe), Value::List(exprs)) => {
+                        match attribute.name.as_str() {
+                            "tags" => recipe.borrow_mut().tags = tags_from_values(exprs),
+                            _ => return Err(FactoryError::Undefined(attribute.name)),
+                        }
+                    },
+                    (Value::Stream(_, stream), Value::List(exprs)) => {
+                        match attribute.name.as_str() {
+                            "tags" => stream.borrow_mut().tags = tags_from_values(exprs),
+                            _ => return Err(FactoryError::Undefined(attribute.name)),
                         }
                     },
                     (Value::Knowledge(_, knowledge, _), Value::List(exprs)) => {
@@ -342,36 +1037,89 @@ impl Factory {
                                     None
                                 }
                             }).collect(),
-                            _ => unimplemented!()
+                            _ => return Err(FactoryError::Undefined(attribute.name)),
                         }
                     }
-                    _ => unimplemented!(),
+                    _ => return Err(FactoryError::TypeError),
                 }
 
-                lhs
+                Ok(lhs)
+            }
+            _ => Err(FactoryError::TypeError),
+        }
+    }
+
+    /// Makes `module` read-only, so products and recipes can't be added to it or redefine ones already in it
+    pub fn lock_module(&mut self, module: &str) {
+        self.locked_modules.insert(module.to_owned());
+    }
+
+    /// Errors if `module` is locked, or if `existing` is the id of a module that's locked
+    fn check_unlocked(&self, module: &str, existing: Option<usize>) -> Result<(), FactoryError> {
+        if self.locked_modules.contains(module) {
+            return Err(FactoryError::ModuleLocked(module.to_owned()));
+        }
+
+        if let Some(existing) = existing {
+            if let Some(locked) = self.locked_modules.iter().find(|locked| self.modules.get(*locked) == Some(&existing)) {
+                return Err(FactoryError::ModuleLocked(locked.to_owned()));
             }
-            (lhs, op, rhs) => panic!("Invalid operation: `{lhs:?} {op:?} {rhs:?}`"),
         }
+
+        Ok(())
     }
 
     fn register_product(&mut self, name: &str, module: &str) -> Result<(), FactoryError> {
-        if self.products.get(name).is_none() || self.unresolved.contains(&name.to_owned()) {
+        let key = &Self::qualified_name(module, name);
+        self.check_unlocked(module, self.products.get(key).map(|product| product.borrow().module))?;
+
+        if self.products.get(key).is_none() || self.unresolved.contains(key) {
             let module_id = self.get_module(module);
             let product_id = self.products.get("__next").map(|i| i.borrow().id).unwrap_or(0);
             let product = Product { id: product_id, module: module_id };
 
             self.products.insert("__next".to_owned(), Rc::new(RefCell::new(Product { id: product_id + 1, module: 0 })));
-            self.products.insert(name.to_owned(), Rc::new(RefCell::new(product)));
+            self.products.insert(key.to_owned(), Rc::new(RefCell::new(product)));
             self.product_names.insert(product, name.to_owned());
 
             Ok(())
         } else {
-            Err(FactoryError::Exists(name.to_owned()))
+            Err(FactoryError::Exists(key.to_owned()))
+        }
+    }
+
+    fn declare_recipe(&mut self, name: &str, module: &str) -> Result<(), FactoryError> {
+        let key = Self::qualified_name(module, name);
+        self.check_unlocked(module, self.recipes.get(&key).map(|recipe| recipe.borrow().module))?;
+
+        // a recipe that's already defined might be getting a variant, which `register_recipe` checks once its inputs are known
+        if self.unresolved.contains(&key) || self.recipes.contains_key(&key) {
+            return Ok(());
         }
+
+        let recipe = Recipe {
+            rate: Rate::ZERO,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            knowledge: Vec::new(),
+            unlocked: true,
+            buildings: 1,
+            tags: HashSet::new(),
+            machine: None,
+            power: None,
+            module: self.get_module(module),
+        };
+
+        self.recipes.insert(key.to_owned(), Rc::new(RefCell::new(recipe)));
+        self.unresolved.push(key);
+        Ok(())
     }
 
-    fn register_recipe(&mut self, name: &str, inputs: Vec<Expr>, outputs: Vec<Expr>, period: Expr, module: &str) -> Result<(), FactoryError> {
-        if self.recipes.get(name).is_none() || self.unresolved.contains(&name.to_owned()) {
+    fn register_recipe(&mut self, name: &str, inputs: Vec<Expr>, outputs: Vec<Expr>, period: Expr, power: Option<Expr>, module: &str) -> Result<(), FactoryError> {
+        let key = &Self::qualified_name(module, name);
+        self.check_unlocked(module, self.recipes.get(key).map(|recipe| recipe.borrow().module))?;
+
+        if self.recipes.get(key).is_none() || self.unresolved.contains(key) {
             let (inputs, knowledge) = self.parts_from_exprs(inputs.clone(), module)?;
 
             if knowledge.len() > 0 {
@@ -379,17 +1127,19 @@ impl Factory {
             }
 
             let (outputs, knowledge) = self.parts_from_exprs(outputs, module)?;
-            let period = self.usize_from_expr(period, module)?;
-            let rate = Rate { amount: 1, ticks: period as f64 };
+            let rate = self.recipe_rate(period, module)?;
+            let power = self.recipe_power(power, module)?;
+            let module_id = self.get_module(module);
 
-            if let Some(r) = self.recipes.get_mut(name) {
+            if let Some(r) = self.recipes.get_mut(key) {
                 let recipe = &mut *r.borrow_mut();
                 recipe.rate = rate;
                 recipe.inputs = inputs;
                 recipe.outputs = outputs;
                 recipe.knowledge = knowledge;
-
-
+                recipe.power = power;
+                self.generation.bump();
+                self.unresolved.retain(|name| name != key);
             } else {
                 let recipe = Recipe {
                     rate,
@@ -397,18 +1147,127 @@ impl Factory {
                     outputs,
                     knowledge,
                     unlocked: true,
+                    buildings: 1,
+                    tags: HashSet::new(),
+                    machine: None,
+                    power,
+                    module: module_id,
                 };
     
-                self.recipes.insert(name.to_owned(), Rc::new(RefCell::new(recipe)));    
+                self.recipes.insert(key.to_owned(), Rc::new(RefCell::new(recipe)));    
             }
 
             Ok(())
         } else {
-            Err(FactoryError::Exists(name.to_owned()))
-        }
+            let (inputs, knowledge) = self.parts_from_exprs(inputs, module)?;
+
+            if !knowledge.is_empty() {
+                return Err(FactoryError::TypeError);
+            }
+
+            let input_set = |inputs: &[RecipePart]| inputs.iter().map(|part| *part.product.borrow()).collect::<HashSet<_>>();
+            let primary = self.recipes[key].clone();
+            let taken = std::iter::once(&primary).chain(self.recipe_variants.get(key).into_iter().flatten())
+                .any(|recipe| input_set(&recipe.borrow().inputs) == input_set(&inputs));
+
+            // the same name with the same inputs is a duplicate rather than a variant
+            if taken {
+                return Err(FactoryError::Exists(key.to_owned()));
+            }
+
+            let (outputs, knowledge) = self.parts_from_exprs(outputs, module)?;
+            let rate = self.recipe_rate(period, module)?;
+            let power = self.recipe_power(power, module)?;
+
+            let variant = Recipe {
+                rate,
+                inputs,
+                outputs,
+                knowledge,
+                // locked or unlocked alongside the first recipe with this name, by the same knowledge
+                unlocked: primary.borrow().unlocked,
+                buildings: 1,
+                tags: HashSet::new(),
+                machine: None,
+                power,
+                module: self.get_module(module),
+            };
+            let variant = Rc::new(RefCell::new(variant));
+
+            for knowledge in self.knowledge.values() {
+                let mut knowledge = knowledge.borrow_mut();
+
+                if knowledge.recipes.iter().any(|recipe| Rc::ptr_eq(recipe, &primary)) {
+                    knowledge.recipes.push(variant.clone());
+                }
+            }
+
+            self.recipe_variants.entry(key.to_owned()).or_default().push(variant);
+            Ok(())
+        }
+    }
+
+    fn recipe_rate(&mut self, period: Expr, module: &str) -> Result<Rate, FactoryError> {
+        match self.process_expr(period, module)?.ok_or(FactoryError::UnexpectedEof)? {
+            Value::Int(period) if period > 0 => Ok(Rate::new(1, period as usize)),
+            Value::Float(period) if period > 0.0 => Ok(Rate::from_f64_ticks(1, period)),
+            // a rate like `60/min` is how many cycles run in that time
+            Value::Rate(rate) if rate.amount > 0 => Ok(rate),
+            Value::Int(_) | Value::Float(_) | Value::Rate(_) => Err(FactoryError::InvalidArguments),
+            _ => Err(FactoryError::TypeError),
+        }
+    }
+
+    fn recipe_power(&mut self, power: Option<Expr>, module: &str) -> Result<Option<f64>, FactoryError> {
+        let Some(power) = power else {
+            return Ok(None);
+        };
+
+        match self.process_expr(power, module)?.ok_or(FactoryError::UnexpectedEof)? {
+            Value::Int(power) => Ok(Some(power as f64)),
+            Value::Float(power) => Ok(Some(power)),
+            _ => Err(FactoryError::TypeError),
+        }
+    }
+
+    /// Key a product or recipe named `name` in `module` is stored under. The base mod's keep their bare names,
+    /// since every module falls back to them, while other modules' are prefixed like streams are
+    pub fn qualified_name(module: &str, name: &str) -> String {
+        if module == "base" {
+            name.to_owned()
+        } else {
+            format!("__{}::{name}", module.to_uppercase())
+        }
+    }
+
+    /// The product `name` as seen from `module`, which is its own if it has one and the base mod's otherwise
+    pub fn find_product(&self, module: &str, name: &str) -> Option<&Rc<RefCell<Product>>> {
+        self.products.get(&Self::qualified_name(module, name)).or_else(|| self.products.get(name))
+    }
+
+    /// The recipe `name` as seen from `module`, which is its own if it has one and the base mod's otherwise
+    pub fn find_recipe(&self, module: &str, name: &str) -> Option<&Rc<RefCell<Recipe>>> {
+        self.recipes.get(&Self::qualified_name(module, name)).or_else(|| self.recipes.get(name))
+    }
+
+    /// Key a stream named `name` in `module` is stored under. Streams from mods are prefixed with the uppercased module like `__BASE::name`,
+    /// while the factory blueprint's own streams keep their bare names
+    pub fn qualified_stream_name(module: &str, name: &str) -> String {
+        if module == "factory" {
+            name.to_owned()
+        } else {
+            format!("__{}::{name}", module.to_uppercase())
+        }
+    }
+
+    /// Looks up the stream `name` registered by `module`
+    pub fn stream(&self, module: &str, name: &str) -> Option<&Rc<RefCell<Stream>>> {
+        self.streams.get(&Self::qualified_stream_name(module, name))
     }
 
     fn register_stream(&mut self, name: &str, expr: Expr, module: &str) -> Result<(), FactoryError> {
+        let name = &Self::qualified_stream_name(module, name);
+
         if self.streams.get(name).is_none() {
             let stream = self.stream_from_expr(expr, module)?;
 
@@ -422,9 +1281,17 @@ impl Factory {
 
     fn register_knowledge(&mut self, name: &str, outputs: Vec<String>, module: &str) -> Result<(), FactoryError> {
         if self.knowledge.get(name).is_none() {
-            let recipes = outputs.into_iter().map(|recipe_name| if let Some(recipe) = self.recipes.get(&recipe_name) {
+            let module_id = self.get_module(module);
+            let recipes = outputs.into_iter().map(|recipe_name| if let Some(recipe) = self.find_recipe(module, &recipe_name) {
+                // only declared so far, so it starts out locked like a recipe the knowledge made up itself
+                if self.unresolved.contains(&Self::qualified_name(module, &recipe_name)) {
+                    recipe.borrow_mut().unlocked = false;
+                }
+
                 recipe.clone()
             } else {
+                // the knowledge's own module is expected to define it later
+                let recipe_name = Self::qualified_name(module, &recipe_name);
                 self.unresolved.push(recipe_name.to_owned()); 
                 let recipe = Recipe {
                     rate: Rate::ZERO,
@@ -432,6 +1299,11 @@ impl Factory {
                     outputs: Vec::new(),
                     knowledge: Vec::new(),
                     unlocked: false,
+                    buildings: 1,
+                    tags: HashSet::new(),
+                    machine: None,
+                    power: None,
+                    module: module_id,
                 };
                 let recipe = Rc::new(RefCell::new(recipe));
 
@@ -439,7 +1311,7 @@ impl Factory {
                 recipe
             }).collect();
             let dependencies = Vec::new();
-            let knowledge = Knowledge { name: name.to_owned(), progress: Buffer { current: 0, max: 1 }, recipes, dependencies, unlocked: false };
+            let knowledge = Knowledge { name: name.to_owned(), progress: Buffer { current: 0, min: 0, max: 1, reserve: false, policy: BufferPolicy::Block }, recipes, dependencies, unlocked: false };
             let knowledge = Rc::new(RefCell::new(knowledge));
 
             self.knowledge.insert(name.to_owned(), knowledge);
@@ -452,7 +1324,8 @@ impl Factory {
         let Some(&id) = self.modules.get(name) else {
             let id = *self.modules.get("__next").unwrap_or(&1);
             self.modules.insert("__next".to_owned(), id + 1);
-            return id 
+            self.modules.insert(name.to_owned(), id);
+            return id
         };
 
         id
@@ -465,7 +1338,7 @@ impl Factory {
             if let Some(value) = self.process_expr(expr, module)? {
                 match value {
                     Value::RecipePart(recipe_part) => parts.push(recipe_part),
-                    Value::Product(_, product) => parts.push(RecipePart { product, amount: 1 }),
+                    Value::Product(_, product) => parts.push(RecipePart { product, amount: 1, voided: false }),
                     Value::Knowledge(_, silly, mult) => knowledge.push((silly, mult)),
                     _ => return Err(FactoryError::TypeError),
                 }
@@ -489,14 +1362,21 @@ impl Factory {
     }
 
     fn stream_from_expr(&mut self, expr: Expr, module: &str) -> Result<Rc<RefCell<Stream>>, FactoryError> {
+        // built here rather than through its value, since other streams can't be bound to a second name
+        if let Expr::Source { product, rate } = expr {
+            return self.source_stream(&product, *rate, module);
+        }
+
         if let Some(value) = self.process_expr(expr, module)? {
             match value {
                 Value::Call(..) => {
                     self.parse_call(value)
                 },
                 Value::MultRecipe(call, mult) => {
-                    self.parse_call(*call).inspect(|stream| stream.borrow_mut().mult = mult)
+                    self.parse_call(*call).inspect(|stream| stream.borrow_mut().set_mult(mult))
                 },
+                // fresh streams like the ones from `with_mult` don't have a name yet
+                Value::Stream(_, stream) if !self.streams.values().any(|named| Rc::ptr_eq(named, &stream)) => Ok(stream),
                 _ => Err(FactoryError::TypeError)
             }
         } else {
@@ -504,6 +1384,36 @@ impl Factory {
         }
     }
 
+    /// Zero-input stream making `product` at `rate`, with a recipe made up for it that isn't registered anywhere
+    fn source_stream(&mut self, product_name: &str, rate: Expr, module: &str) -> Result<Rc<RefCell<Stream>>, FactoryError> {
+        let product = self.find_product(module, product_name).ok_or_else(|| FactoryError::Undefined(product_name.to_owned()))?.clone();
+
+        let rate = match self.process_expr(rate, module)?.ok_or(FactoryError::UnexpectedEof)? {
+            Value::Rate(rate) if rate.amount > 0 => rate,
+            Value::Rate(_) => return Err(FactoryError::InvalidArguments),
+            _ => return Err(FactoryError::TypeError),
+        };
+
+        // one of the product per cycle, so the recipe's cycle rate is the product's rate
+        let recipe = Recipe {
+            rate,
+            inputs: Vec::new(),
+            outputs: vec![RecipePart { product, amount: 1, voided: false }],
+            knowledge: Vec::new(),
+            unlocked: true,
+            buildings: 1,
+            tags: HashSet::new(),
+            machine: None,
+            power: None,
+            module: self.get_module(module),
+        };
+
+        let mut stream = self.build_stream(Rc::new(RefCell::new(recipe)), InputStreams::NONE);
+        stream.source = true;
+
+        Ok(Rc::new(RefCell::new(stream)))
+    }
+
     fn parse_call(&mut self, call: Value) -> Result<Rc<RefCell<Stream>>, FactoryError> {
         let Value::Call(lhs, rhs) = call else {
             return Err(FactoryError::TypeError);
@@ -513,59 +1423,352 @@ impl Factory {
             return Err(FactoryError::TypeError)
         };
 
-        let mut inputs = Vec::with_capacity(rhs.len());
+        let recipe = self.choose_variant(recipe, &rhs)?;
+        let rhs = self.order_keyword_args(&recipe, rhs)?;
 
         if rhs.len() != recipe.borrow().inputs.len() {
             return Err(FactoryError::InvalidArguments);
         }
 
+        // raw extractors like `Iron()` take nothing, so they only get output buffers
+        if rhs.is_empty() {
+            return Ok(Rc::new(RefCell::new(self.build_stream(recipe, InputStreams::NONE))));
+        }
+
+        let mut inputs = Vec::with_capacity(rhs.len());
+
         for (idx, value) in rhs.into_iter().enumerate() {
             let product = recipe.borrow().inputs[idx].product.clone();
 
-            match value {
-                Value::Stream(_, stream) => inputs.push((product, stream)),
-                Value::Call(..) => {
-                    inputs.push((product, self.parse_call(value)?));
-                },
-                Value::MultRecipe(call, mult) => {
-                    inputs.push((product, self.parse_call(*call).inspect(|stream| stream.borrow_mut().mult = mult)?));
-                },
-                _ => {
-                    println!("{value}");
-                    return Err(FactoryError::TypeError)
-                },
+            // a list feeds the input from several streams in parallel, and their rates are summed
+            let sources = match value {
+                Value::List(sources) if !sources.is_empty() => sources,
+                Value::List(_) => return Err(FactoryError::InvalidArguments),
+                value => vec![value],
+            };
+
+            for value in sources {
+                // a capped link still builds its source like any other argument
+                let (value, cap) = match value {
+                    Value::Limited(inner, cap) => (*inner, Some(cap)),
+                    value => (value, None),
+                };
+
+                inputs.push((product.clone(), self.input_stream(value)?, cap));
             }
         }
 
+        Ok(Rc::new(RefCell::new(self.build_stream(recipe, inputs))))
+    }
+
+    /// Stream feeding a recipe argument, building it first if it's a call
+    fn input_stream(&mut self, value: Value) -> Result<Rc<RefCell<Stream>>, FactoryError> {
+        match value {
+            Value::Stream(_, stream) => Ok(stream),
+            Value::Call(..) => self.parse_call(value),
+            Value::MultRecipe(call, mult) => self.parse_call(*call).inspect(|stream| stream.borrow_mut().set_mult(mult)),
+            _ => Err(FactoryError::TypeError),
+        }
+    }
+
+    /// The variant of `recipe` that takes `args`, going by what each argument outputs or the names they're passed by.
+    /// Recipes without variants are returned as they are, so their calls fail the same way they always have
+    fn choose_variant(&self, recipe: Rc<RefCell<Recipe>>, args: &[Value]) -> Result<Rc<RefCell<Recipe>>, FactoryError> {
+        let Some(key) = self.recipe_name(&recipe) else {
+            return Ok(recipe);
+        };
+
+        let Some(variants) = self.recipe_variants.get(&key) else {
+            return Ok(recipe);
+        };
+
+        let mut candidates: Vec<_> = std::iter::once(&recipe).chain(variants)
+            .filter(|candidate| self.takes(candidate, args))
+            .cloned()
+            .collect();
+
+        match candidates.len() {
+            0 => Err(FactoryError::InvalidArguments),
+            1 => Ok(candidates.remove(0)),
+            _ => {
+                let candidates = candidates.iter().map(|candidate| {
+                    let inputs = candidate.borrow().inputs.iter()
+                        .map(|part| self.product_label(*part.product.borrow()))
+                        .collect::<Vec<_>>();
+
+                    format!("{}({})", Self::unqualified_name(&key), inputs.join(", "))
+                }).collect();
+
+                Err(FactoryError::AmbiguousRecipe(key, candidates))
+            },
+        }
+    }
+
+    /// Whether every input of `recipe` is supplied by the argument going to it
+    fn takes(&self, recipe: &Rc<RefCell<Recipe>>, args: &[Value]) -> bool {
+        let Ok(args) = self.order_keyword_args(recipe, args.to_vec()) else {
+            return false;
+        };
+
+        let recipe = recipe.borrow();
+        args.len() == recipe.inputs.len()
+            && args.iter().zip(&recipe.inputs).all(|(arg, input)| self.supplies(arg, &input.product.borrow()))
+    }
+
+    /// Whether `value` outputs `product` when passed to a recipe
+    fn supplies(&self, value: &Value, product: &Product) -> bool {
+        let makes = |recipe: &Rc<RefCell<Recipe>>| recipe.borrow().outputs.iter().any(|output| *output.product.borrow() == *product);
+
+        match value {
+            Value::Stream(_, stream) => makes(&stream.borrow().recipe),
+            // the call hasn't picked its own variant yet, so any of them will do
+            Value::Call(lhs, _) => match &**lhs {
+                Value::Recipe(_, recipe) => makes(recipe) || self.recipe_name(recipe)
+                    .and_then(|key| self.recipe_variants.get(&key))
+                    .is_some_and(|variants| variants.iter().any(makes)),
+                _ => false,
+            },
+            Value::MultRecipe(inner, _) | Value::Limited(inner, _) => self.supplies(inner, product),
+            Value::List(sources) => !sources.is_empty() && sources.iter().all(|source| self.supplies(source, product)),
+            _ => false,
+        }
+    }
+
+    /// Puts arguments passed by name into the order of the recipe's inputs they name. Positional arguments are returned as they are,
+    /// and mixing the two styles, naming something that isn't an input, or leaving an input out is an error
+    fn order_keyword_args(&self, recipe: &Rc<RefCell<Recipe>>, args: Vec<Value>) -> Result<Vec<Value>, FactoryError> {
+        let named = args.iter().filter(|arg| matches!(arg, Value::KeywordArg(..))).count();
+
+        if named == 0 {
+            return Ok(args);
+        } else if named != args.len() {
+            return Err(FactoryError::InvalidArguments);
+        }
+
+        let inputs = recipe.borrow().inputs.clone();
+        let mut ordered: Vec<Option<Value>> = vec![None; inputs.len()];
+
+        for arg in args {
+            let Value::KeywordArg(name, value) = arg else {
+                return Err(FactoryError::InvalidArguments);
+            };
+
+            // the same product can be an input twice, so each name fills the first of its slots that's still empty
+            let idx = (0..inputs.len())
+                .find(|&idx| ordered[idx].is_none() && self.product_names.get(&inputs[idx].product.borrow()) == Some(&name))
+                .ok_or(FactoryError::InvalidArguments)?;
+
+            ordered[idx] = Some(*value);
+        }
+
+        ordered.into_iter().collect::<Option<Vec<Value>>>().ok_or(FactoryError::InvalidArguments)
+    }
+
+    /// Creates a stream running `recipe` at mult 1, with buffers sized by the profile for every input and output
+    fn build_stream(&self, recipe: Rc<RefCell<Recipe>>, inputs: impl Into<InputStreams>) -> Stream {
         let mut buffer = HashMap::new();
 
         for output in &recipe.borrow().outputs {
             let product = output.product.borrow().clone();
-            buffer.insert(product, Buffer { current: 0, max: output.amount * DEFAULT_BUF_MULT});
+            buffer.insert(product, Buffer { current: 0, min: 0, max: output.amount * self.profile.buffer_mult, reserve: false, policy: BufferPolicy::Block });
+        }
+
+        // input buffers are created up front so they can be resized before the first tick
+        for input in &recipe.borrow().inputs {
+            let product = *input.product.borrow();
+            let max = recipe.borrow().required_of(&product).unwrap() * self.profile.buffer_mult;
+            buffer.entry(product).or_insert(Buffer { current: 0, min: 0, max, reserve: false, policy: BufferPolicy::Block });
+        }
+
+        let ticks = recipe.borrow().rate.period().ceil() as usize;
+        Stream {
+            mult: Mult::ONE,
+            recipe,
+            inputs: inputs.into(),
+            buffers: buffer,
+            next: None,
+            ticks,
+            allow: HashSet::new(),
+            tags: HashSet::new(),
+            source: false,
+            carry: 0,
+            generation: self.generation.clone(),
+            efficiency_cache: EfficiencyCache::default(),
+        }
+    }
+
+    /// Builds and registers a stream without going through a blueprint, like `let <name> = <recipe_name>(<inputs>) * <mult>;`.
+    /// Each input names the recipe input product it supplies, and `recipe_name` is found the way the factory file would find it.
+    /// Only [`FactoryBuilder`] needs this, so it's gated the same way
+    #[cfg(any(test, feature = "testing"))]
+    pub fn add_stream(&mut self, name: &str, recipe_name: &str, inputs: Vec<(Product, Rc<RefCell<Stream>>)>, mult: usize) -> Result<Rc<RefCell<Stream>>, FactoryError> {
+        if self.streams.contains_key(name) {
+            return Err(FactoryError::Exists(name.to_owned()));
         }
 
-        let ticks = recipe.borrow().rate.ticks as usize;
-        Ok(Rc::new(RefCell::new(Stream { mult: 1, recipe: recipe.clone(), inputs: inputs.into(), buffers: buffer, next: None, ticks })))
+        let recipe = self.find_recipe("factory", recipe_name).ok_or(FactoryError::InvalidArguments)?.clone();
+
+        if inputs.len() != recipe.borrow().inputs.len() || mult == 0 {
+            return Err(FactoryError::InvalidArguments);
+        }
+
+        let inputs = inputs.into_iter().map(|(product, stream)| {
+            let part = recipe.borrow().inputs.iter().find(|part| *part.product.borrow() == product).cloned();
+            part.map(|part| (part.product, stream)).ok_or(FactoryError::InvalidArguments)
+        }).collect::<Result<Vec<_>, _>>()?;
+
+        let mut stream = self.build_stream(recipe, inputs);
+        stream.set_mult(mult);
+
+        let stream = Rc::new(RefCell::new(stream));
+        self.streams.insert(name.to_owned(), stream.clone());
+
+        Ok(stream)
     }
 
     pub fn call(&mut self, method: Method, args: Vec<Value>) -> Result<Option<Value>, FactoryError> {
         match (method.object, method.name) {
             (Value::Stream(stream_name, stream), name) => {
                 match name.as_ref() {
-                    "buffer" => match args.as_slice() {
-                        &[Value::Product(_, ref product), Value::Int(buffer)] => {
-                            stream.borrow_mut().buffers.get_mut(&product.borrow()).unwrap().max = buffer as usize;
+                    "buffer" => match *args.as_slice() {
+                        [Value::Product(ref name, ref product), Value::Int(buffer)] => {
+                            let mut stream = stream.borrow_mut();
+                            let buffer_ref = stream.buffers.get_mut(&product.borrow()).ok_or_else(|| FactoryError::Unbuffered(name.to_owned()))?;
+                            buffer_ref.max = buffer as usize;
+                            Ok(None)
+                        },
+                        // keep at least <min> buffered for the stream, which consumers won't draw below
+                        [Value::Product(ref name, ref product), Value::Int(min), Value::Int(max)] if min <= max => {
+                            let mut stream = stream.borrow_mut();
+                            let buffer_ref = stream.buffers.get_mut(&product.borrow()).ok_or_else(|| FactoryError::Unbuffered(name.to_owned()))?;
+                            buffer_ref.min = min as usize;
+                            buffer_ref.max = max as usize;
+                            Ok(None)
+                        },
+                        // hold <duration> worth of the stream's throughput, e.g. `2/min` for two minutes
+                        [Value::Product(ref name, ref product), Value::Rate(duration)] => {
+                            let mut stream = stream.borrow_mut();
+                            let product = *product.borrow();
+                            let rate = stream.rate_of(&product).or_else(|| stream.optimal_inflow_of(&product)).ok_or_else(|| FactoryError::Unbuffered(name.to_owned()))?;
+                            let minutes = duration.amount as f64 * duration.ticks as f64 / (self.profile.ticks_per_second * 60.0);
+                            let buffer_ref = stream.buffers.get_mut(&product).ok_or_else(|| FactoryError::Unbuffered(name.to_owned()))?;
+                            buffer_ref.max = (rate.per_minute(self.profile.ticks_per_second) * minutes).ceil() as usize;
+                            Ok(None)
+                        },
+                        _ => Err(FactoryError::InvalidArguments)
+                    },
+                    // pre-stock <amount> of an input that upstream never refills
+                    "reserve" => match args.as_slice() {
+                        &[Value::Product(ref name, ref product), Value::Int(amount)] => {
+                            let mut stream = stream.borrow_mut();
+                            let buffer_ref = stream.buffers.get_mut(&product.borrow()).ok_or_else(|| FactoryError::Unbuffered(name.to_owned()))?;
+                            *buffer_ref = Buffer { current: amount as usize, max: amount as usize, reserve: true, ..*buffer_ref };
                             Ok(None)
                         },
                         _ => Err(FactoryError::InvalidArguments)
                     },
+                    "efficiency" => match args.as_slice() {
+                        &[] => Ok(Some(Value::Float(self.efficiency_of(&stream.borrow())))),
+                        _ => Err(FactoryError::InvalidArguments),
+                    },
+                    // current rate of one output, at the stream's efficiency
+                    "rate" => match args.as_slice() {
+                        [Value::Product(_, product)] => {
+                            let rate = stream.borrow().rate_of(&product.borrow()).ok_or(FactoryError::InvalidArguments)?;
+                            Ok(Some(Value::Rate(rate)))
+                        },
+                        _ => Err(FactoryError::InvalidArguments),
+                    },
+                    // what a buffer does once it's full, one of block, overflow or void
+                    "policy" => {
+                        let [Value::Product(name, product), Value::String(policy)] = &args[..] else {
+                            return Err(FactoryError::InvalidArguments);
+                        };
+
+                        let policy = policy.parse().map_err(|_| FactoryError::InvalidArguments)?;
+                        let mut stream = stream.borrow_mut();
+                        let buffer = stream.buffers.get_mut(&product.borrow()).ok_or_else(|| FactoryError::Unbuffered(name.to_owned()))?;
+                        buffer.policy = policy;
+
+                        Ok(None)
+                    },
+                    // throw away the given outputs once their buffers are full instead of stalling the stream
+                    "void" => {
+                        if args.is_empty() {
+                            return Err(FactoryError::InvalidArguments);
+                        }
+
+                        for arg in args {
+                            let Value::Product(name, product) = arg else {
+                                return Err(FactoryError::InvalidArguments);
+                            };
+
+                            if stream.borrow().recipe.borrow().optimal_outflow_of(&product.borrow()).is_none() {
+                                return Err(FactoryError::Unbuffered(name));
+                            }
+
+                            stream.borrow_mut().buffers.get_mut(&product.borrow()).unwrap().policy = BufferPolicy::Overflow;
+                        }
+
+                        Ok(None)
+                    },
+                    "allow" => {
+                        for arg in args {
+                            let Value::String(kind) = arg else {
+                                return Err(FactoryError::InvalidArguments);
+                            };
+
+                            let kind = kind.parse().map_err(|_| FactoryError::UnknownWarning(kind))?;
+                            stream.borrow_mut().allow.insert(kind);
+                        }
+
+                        Ok(None)
+                    },
                     "solve" => match args.as_slice() {
                         &[] => {
+                            self.validate_acyclic()?;
                             self.solve(stream.clone());
+                            self.apply_machine_budgets()?;
+                            self.apply_raw_supply()?;
                             Ok(None)
                         }
                         _ => Err(FactoryError::InvalidArguments)
                     },
+                    // copy of the stream running `n` machines, like writing its call out again with `* n`
+                    "with_mult" => {
+                        let mult = match args.as_slice() {
+                            [Value::Int(mult)] if *mult > 0 => Mult::from(*mult as usize),
+                            [Value::Float(mult)] if *mult > 0.0 => Mult::from_f64(*mult),
+                            _ => return Err(FactoryError::InvalidArguments),
+                        };
+
+                        if mult.is_zero() {
+                            return Err(FactoryError::InvalidArguments);
+                        }
+
+                        if !mult.is_whole() && !self.profile.fractional_machines {
+                            return Err(FactoryError::FractionalMult);
+                        }
+
+                        let instance = self.instance_of(&stream);
+                        instance.borrow_mut().set_mult(mult);
+
+                        Ok(Some(Value::Stream("<unnamed>".to_owned(), instance)))
+                    },
+                    // sets the mult to make at least the given rate of a product, then solves for it
+                    "scale_to" => match args.as_slice() {
+                        [Value::Product(_, product), Value::Rate(target)] => {
+                            let mult = stream.borrow().mult_for(&product.borrow(), *target).ok_or(FactoryError::InvalidArguments)?;
+                            stream.borrow_mut().set_mult(mult);
+
+                            self.validate_acyclic()?;
+                            self.solve(stream.clone());
+                            self.apply_machine_budgets()?;
+                            self.apply_raw_supply()?;
+                            Ok(None)
+                        },
+                        _ => Err(FactoryError::InvalidArguments),
+                    },
                     "log" => {
                         let (inputs, outputs) = if args.len() == 0 {
                             (stream.borrow().recipe.borrow().inputs.clone(), stream.borrow().recipe.borrow().outputs.clone())
@@ -589,156 +1792,1451 @@ impl Factory {
                            (inputs, outputs)
                         };
 
-                        println!("----- {stream_name} x{} -----", stream.borrow().mult);
+                        if stream.borrow().recipe.borrow().buildings > 1 {
+                            println!("----- {stream_name} x{} ({} buildings) -----", stream.borrow().mult(), stream.borrow().buildings());
+                        } else {
+                            println!("----- {stream_name} x{} -----", stream.borrow().mult());
+                        }
                         for input in inputs {
                             let rate = stream.borrow().optimal_inflow_of(&*input.product.borrow()).unwrap();
-                            let name = self.product_names.get(&*input.product.borrow()).unwrap();
+                            let name = self.product_label(*input.product.borrow());
                             println!("  <- {} @ {}", name, rate);
                         }
 
+                        let rates = stream.borrow().output_rates_map();
+
                         for output in outputs {
                             // if the product isnt in the stream something went wrong so a panic is actually desired
-                            let rate = stream.borrow().rate_of(&*output.product.borrow()).unwrap();
-                            let name = self.product_names.get(&*output.product.borrow()).unwrap();
+                            let rate = rates.get(&*output.product.borrow()).unwrap().unwrap();
+                            let name = self.product_label(*output.product.borrow());
                             println!("  -> {} @ {}", name, rate);
                         }
 
+                        if let Some((product, efficiency)) = stream.borrow().limiting_input().filter(|(_, efficiency)| *efficiency < 1.0) {
+                            println!("  limited by {} @ {:.0}%", self.product_label(product), efficiency * 100.0);
+                        }
+
+                        Ok(None)
+                    }
+                    // how much of each output (or just the given products) goes unconsumed
+                    "surplus" => {
+                        let mut products: Vec<Product> = Vec::new();
+
+                        if args.is_empty() {
+                            for output in stream.borrow().recipe.borrow().outputs.iter().filter(|output| !output.voided) {
+                                products.push(*output.product.borrow());
+                            }
+                        } else {
+                            for arg in args {
+                                let Value::Product(name, product) = arg else {
+                                    return Err(FactoryError::InvalidArguments);
+                                };
+
+                                if stream.borrow().recipe.borrow().optimal_outflow_of(&product.borrow()).is_none() {
+                                    return Err(FactoryError::Unbuffered(name));
+                                }
+
+                                products.push(*product.borrow());
+                            }
+                        }
+
+                        println!("----- {stream_name} surplus -----");
+
+                        for product in products {
+                            let surplus = Rate::from_per_tick(self.surplus_of(&stream, &product));
+                            let name = self.product_label(product);
+                            println!("  -> {} @ {}", name, surplus);
+                        }
+
                         Ok(None)
                     }
-                    _ => unimplemented!()
+                    _ => Err(FactoryError::Undefined(name)),
+                }
+            },
+            (Value::Recipe(_, recipe), name) => {
+                match (name.as_ref(), args.as_slice()) {
+                    ("period", &[]) => {
+                        let period = recipe.borrow().rate.period();
+
+                        if period.fract() == 0.0 {
+                            Ok(Some(Value::Int(period as isize)))
+                        } else {
+                            Ok(Some(Value::Float(period)))
+                        }
+                    },
+                    // how many of the first output come out per one of the second
+                    ("ratio", &[Value::Product(_, ref numerator), Value::Product(_, ref denominator)]) => {
+                        recipe.borrow().output_ratio(*numerator.borrow(), *denominator.borrow())
+                            .map(|ratio| Some(Value::Float(ratio)))
+                            .ok_or(FactoryError::InvalidArguments)
+                    },
+                    _ => Err(FactoryError::InvalidArguments),
+                }
+            },
+            (Value::Tapped(tapped), name) => {
+                match (name.as_ref(), args.as_slice()) {
+                    // what's left of each output after the taps
+                    ("log", &[]) => {
+                        println!("----- {} (tapped) -----", tapped.name);
+
+                        for output in tapped.source.borrow().recipe.borrow().outputs.iter().filter(|output| !output.voided) {
+                            let product = *output.product.borrow();
+                            let name = self.product_label(product);
+                            println!("  -> {} @ {}", name, tapped.rate_of(&product).unwrap());
+                        }
+
+                        Ok(None)
+                    },
+                    ("rate", [Value::Product(_, product)]) => {
+                        tapped.rate_of(&product.borrow()).map(|rate| Some(Value::Rate(rate))).ok_or(FactoryError::InvalidArguments)
+                    },
+                    _ => Err(FactoryError::InvalidArguments),
                 }
             },
-            _ => unimplemented!()
+            _ => Err(FactoryError::TypeError),
         }
     }
 
-    pub fn tick(&mut self, ticks: usize) {
-        self.tick += ticks;
-        println!();
-        println!("[-- Tick {} --]", self.tick);
+    /// Current value of an attribute, so accesses can be chained through it like `stream.recipe.period()`
+    pub fn attribute_value(&self, attribute: Attribute) -> Result<Value, FactoryError> {
+        match (attribute.object, attribute.name.as_ref()) {
+            (Value::Stream(_, stream), "recipe") => {
+                let recipe = stream.borrow().recipe.clone();
+                let name = self.recipe_name(&recipe).unwrap_or_else(|| "<unnamed>".to_owned());
 
-        for stream in self.streams.values() {
-            {
-                // don't run streams that only output unlocked knowledge with no products, or that would output locked knowledge
-                let stream = stream.borrow();
-                let recipe = stream.recipe.borrow();
-                if ((recipe.knowledge.iter().any(|knowledge| !knowledge.0.borrow().unlockable())
-                    || recipe.knowledge.iter().all(|knowledge| knowledge.0.borrow().unlocked)
-                ) && recipe.outputs.len() == 0) || !recipe.unlocked {
-                    continue;
-                }
-            }
+                Ok(Value::Recipe(name, recipe))
+            },
+            (Value::Stream(_, stream), "tags") => Ok(tags_to_value(&stream.borrow().tags)),
+            (Value::Recipe(_, recipe), "buildings") => Ok(Value::Int(recipe.borrow().buildings as isize)),
+            (Value::Recipe(_, recipe), "machine") => recipe.borrow().machine.clone().map(Value::String).ok_or(FactoryError::TypeError),
+            (Value::Recipe(_, recipe), "tags") => Ok(tags_to_value(&recipe.borrow().tags)),
+            (Value::Knowledge(_, knowledge, _), "threshold") => Ok(Value::Int(knowledge.borrow().progress.max as isize)),
+            _ => Err(FactoryError::TypeError),
+        }
+    }
 
-            // 1. tick stream if in progress
-            // 2. fill output buffers if stream finishes
-            // 2a. fill input buffers
-            // 2b. start stream again if inputs satisfied
-            // 3. repeat as many times as can fit within <ticks> ticks
+    /// Copies the factory with its own streams and recipes, so mutating the copy leaves `self` untouched.
+    /// Products and knowledge are still shared with `self`
+    pub fn deep_clone(&self) -> Factory {
+        let mut recipes = HashMap::with_capacity(self.recipes.len());
+        let mut cloned_recipes = Vec::with_capacity(self.recipes.len());
 
-            let mut ticks = ticks;
-            let mut cycles = 0;
-            let reset = stream.borrow().ticks;
-            let mut next = stream.borrow().next.unwrap_or(reset);
-            let old_next = next;
-
-            while ticks > 0 {
-                if ticks > reset {
-                    cycles += ticks / reset;
-                    ticks = ticks % reset;
-                } else if ticks >= next {
-                    let i = next;
-                    next = reset - (ticks - next);
-                    ticks -= i;
-                    cycles += 1;
-                } else {
-                    next -= ticks;
-                    ticks = 0;
-                }
-            }
+        for (name, recipe) in &self.recipes {
+            let clone = Rc::new(RefCell::new(recipe.borrow().clone()));
+            cloned_recipes.push((recipe.clone(), clone.clone()));
+            recipes.insert(name.to_owned(), clone);
+        }
 
-            stream.borrow_mut().next = Some(next);
+        let recipe_variants = self.recipe_variants.iter().map(|(name, variants)| {
+            let clones = variants.iter().map(|recipe| {
+                let clone = Rc::new(RefCell::new(recipe.borrow().clone()));
+                cloned_recipes.push((recipe.clone(), clone.clone()));
+                clone
+            }).collect();
 
-            let mut produced: Vec<RecipePart> = stream.borrow().recipe.borrow().outputs.clone().iter().map(|output| RecipePart { product: output.product.clone(), amount: 0 }).collect();
-            let mult = stream.borrow().mult;
-            let mut successful = false;
+            (name.to_owned(), clones)
+        }).collect();
 
-            for _ in 0..cycles {
-                for output in &stream.borrow().recipe.borrow().outputs {
-                    let mut mut_stream = stream.borrow_mut();
-                    let existing = mut_stream.buffers.get_mut(&*output.product.borrow()).unwrap();
-    
-                    existing.current += output.amount * stream.borrow().mult;
-                }
-    
-                for (knowledge, amount) in &stream.borrow().recipe.borrow().knowledge {
-                    if knowledge.borrow().unlockable() {
-                        knowledge.borrow_mut().progress_by(amount * stream.borrow().mult);
-                    }
-                }
+        let mut cloned_streams = Vec::with_capacity(self.streams.len());
+        let streams = self.streams.iter().map(|(name, stream)| {
+            (name.to_owned(), Self::deep_clone_stream(stream, &mut cloned_streams, &cloned_recipes))
+        }).collect();
 
-                for (idx, output) in stream.borrow().recipe.borrow().outputs.iter().enumerate() {
-                    if produced[idx].product == output.product {
-                        produced[idx].amount += output.amount;
-                    } else {
-                        produced.iter_mut().find(|produced| produced.product == output.product).unwrap().amount += output.amount;
-                    }
-                }
+        let generation = EfficiencyGeneration::default();
 
-                for (knowledge, amount) in stream.borrow().recipe.borrow().knowledge.iter() {
-                    let knowledge = knowledge.borrow();
-                    println!("[-- Tick {} --] Learned {} x{} ({})", self.tick, knowledge.name, amount * mult, knowledge.progress);
-                }
+        for (_, clone) in &cloned_streams {
+            clone.borrow_mut().set_generation(generation.clone());
+        }
 
-                let inputs = stream.borrow().inputs.clone();
+        Factory {
+            recipes,
+            recipe_variants,
+            streams,
+            generation,
+            ..self.clone()
+        }
+    }
 
-                for (product, input) in inputs.inner {
-                    if let Some(buffer) = input.borrow_mut().buffers.get_mut(&*product.borrow()) {
-                        let mut own_buffer = stream.borrow().buffers.get(&*product.borrow()).cloned().unwrap_or_else(|| {
-                            let max = stream.borrow().recipe.borrow().required_of(&*product.borrow()).unwrap() * DEFAULT_BUF_MULT * mult;
+    /// Copy of `stream` with empty buffers, as if its call had been written out again.
+    /// Unnamed streams it takes from are copied the same way, while named ones and the recipe are shared with the original
+    pub fn instance_of(&self, stream: &Rc<RefCell<Stream>>) -> Rc<RefCell<Stream>> {
+        let mut instance = stream.borrow().clone();
 
-                            Buffer { current: 0, max }
-                        });
+        let inputs: Vec<_> = instance.inputs().inner.iter().map(|(product, input, cap)| {
+            let input = if self.streams.values().any(|named| Rc::ptr_eq(named, input)) {
+                input.clone()
+            } else {
+                self.instance_of(input)
+            };
 
-                        own_buffer.fill_from(buffer);
-                        stream.borrow_mut().buffers.insert(*&*product.borrow(), own_buffer);
-                    }
-                }
+            (product.clone(), input, *cap)
+        }).collect();
 
-                if !stream.borrow_mut().try_start_produce() {
-                    // can't produce another batch
-                    stream.borrow_mut().next = None;
-                    break;
-                }
-            }
+        instance.set_inputs(inputs.into());
+        instance.next = None;
+        instance.carry = 0;
+        instance.efficiency_cache = EfficiencyCache::default();
 
-            for output in produced {
-                if output.amount > 0 {
-                    println!("[-- Tick {} --] Produced {} x{}", self.tick, self.product_names.get(&*output.product.borrow()).unwrap(), output.amount * mult);
-                }
-            }
+        // reserves are stocked up front rather than filled, so they start out full again
+        for buffer in instance.buffers.values_mut().filter(|buffer| !buffer.reserve) {
+            buffer.current = 0;
         }
+
+        Rc::new(RefCell::new(instance))
     }
-}
 
-impl Value {
-    pub fn access(&self, rhs: &str) -> Value {
-        match self {
-            Self::Stream(..) => {
+    /// Clones `stream` and everything upstream of it into fresh `Rc`s.
+    /// Streams already in `cloned` are reused so shared inputs stay shared, and recipes are swapped for their clone in `recipes` if present
+    pub fn deep_clone_stream(
+        stream: &Rc<RefCell<Stream>>,
+        cloned: &mut Vec<ClonePair<Stream>>,
+        recipes: &[ClonePair<Recipe>],
+    ) -> Rc<RefCell<Stream>> {
+        if let Some((_, clone)) = cloned.iter().find(|(original, _)| Rc::ptr_eq(original, stream)) {
+            return clone.clone();
+        }
+
+        let inputs: Vec<_> = stream.borrow().inputs().inner.iter().map(|(product, input, cap)| {
+            (product.clone(), Self::deep_clone_stream(input, cloned, recipes), *cap)
+        }).collect();
+
+        let mut clone = stream.borrow().clone();
+        clone.set_inputs(inputs.into());
+
+        if let Some((_, recipe)) = recipes.iter().find(|(original, _)| Rc::ptr_eq(original, &clone.recipe)) {
+            clone.recipe = recipe.clone();
+        }
+
+        let clone = Rc::new(RefCell::new(clone));
+        cloned.push((stream.clone(), clone.clone()));
+        clone
+    }
+
+    /// Folds `other`'s products, recipes, knowledge and streams into this factory.
+    /// Modules are matched by name, so `other`'s module ids are remapped onto this factory's, and its products get fresh ids and `Rc`s
+    /// that its recipes and streams are relinked to. Its machine budgets and raw supply only fill in what isn't set here.
+    /// Nothing is merged if any name is already taken
+    pub fn merge(&mut self, other: Factory) -> Result<(), FactoryError> {
+        let clash = other.products.keys().filter(|name| *name != "__next").find(|name| self.products.contains_key(*name))
+            .or_else(|| other.recipes.keys().find(|name| self.recipes.contains_key(*name)))
+            .or_else(|| other.knowledge.keys().find(|name| self.knowledge.contains_key(*name)))
+            .or_else(|| other.streams.keys().find(|name| self.streams.contains_key(*name)));
+
+        if let Some(name) = clash {
+            return Err(FactoryError::Exists(name.to_owned()));
+        }
+
+        if let Some(locked) = other.modules.keys().find(|module| self.locked_modules.contains(*module)) {
+            return Err(FactoryError::ModuleLocked(locked.to_owned()));
+        }
+
+        // its streams' caches are counted with everything else here from now on
+        for stream in other.all_streams() {
+            stream.borrow_mut().set_generation(self.generation.clone());
+        }
+
+        let mut module_ids = HashMap::new();
+
+        for (name, &id) in other.modules.iter().filter(|(name, _)| *name != "__next") {
+            module_ids.insert(id, self.get_module(name));
+        }
+
+        let mut next_id = self.products.get("__next").map(|next| next.borrow().id).unwrap_or(0);
+        let mut relinked: HashMap<Product, Rc<RefCell<Product>>> = HashMap::new();
+
+        for (name, product) in other.products.iter().filter(|(name, _)| *name != "__next") {
+            let old = *product.borrow();
+            let new = Product { id: next_id, module: module_ids.get(&old.module).copied().unwrap_or(old.module) };
+            let new_rc = Rc::new(RefCell::new(new));
+            next_id += 1;
+
+            if let Some(label) = other.product_names.get(&old) {
+                self.product_names.insert(new, label.to_owned());
+            }
+
+            self.products.insert(name.to_owned(), new_rc.clone());
+            relinked.insert(old, new_rc);
+        }
+
+        self.products.insert("__next".to_owned(), Rc::new(RefCell::new(Product { id: next_id, module: 0 })));
+
+        let relink = |product: &Rc<RefCell<Product>>| relinked.get(&*product.borrow()).cloned().unwrap_or_else(|| product.clone());
+        let remap = |product: Product| relinked.get(&product).map(|new| *new.borrow()).unwrap_or(product);
+
+        let relink_recipe = |recipe: &Rc<RefCell<Recipe>>| {
+            let recipe = &mut *recipe.borrow_mut();
+            recipe.module = module_ids.get(&recipe.module).copied().unwrap_or(recipe.module);
+
+            for part in recipe.inputs.iter_mut().chain(recipe.outputs.iter_mut()) {
+                part.product = relink(&part.product);
+            }
+        };
+
+        for (name, recipe) in other.recipes {
+            relink_recipe(&recipe);
+            self.recipes.insert(name, recipe);
+        }
+
+        for (name, variants) in other.recipe_variants {
+            variants.iter().for_each(relink_recipe);
+            self.recipe_variants.insert(name, variants);
+        }
+
+        for (name, stream) in other.streams {
+            let mut inner = stream.borrow_mut();
+            let inputs: Vec<_> = inner.inputs().inner.iter().map(|(product, input, cap)| (relink(product), input.clone(), *cap)).collect();
+
+            inner.set_inputs(inputs.into());
+            inner.buffers = inner.buffers.drain().map(|(product, buffer)| (remap(product), buffer)).collect();
+            drop(inner);
+
+            self.streams.insert(name, stream);
+        }
+
+        self.knowledge.extend(other.knowledge);
+        self.unresolved.extend(other.unresolved);
+
+        for (machine, budget) in other.machine_budgets {
+            self.machine_budgets.entry(machine).or_insert(budget);
+        }
+
+        for (product, rate) in other.raw_supply {
+            self.raw_supply.entry(remap(product)).or_insert(rate);
+        }
+
+        self.stream_targets.extend(other.stream_targets);
+
+        self.invalidate_efficiencies();
+        Ok(())
+    }
+
+    /// Changes how many ticks one cycle of `recipe_name` takes, updating every stream running it.
+    /// The factory file's own recipe is changed if it has one by that name, and the base mod's otherwise
+    pub fn set_recipe_period(&mut self, recipe_name: &str, ticks: f64) -> Result<(), FactoryError> {
+        let recipe = self.find_recipe("factory", recipe_name).ok_or(FactoryError::InvalidArguments)?.clone();
+        recipe.borrow_mut().rate = Rate::from_f64_ticks(1, ticks);
+
+        for stream in self.all_streams() {
+            if Rc::ptr_eq(&stream.borrow().recipe, &recipe) {
+                let mut stream = stream.borrow_mut();
+                stream.ticks = stream.cycle_rate().period().ceil() as usize;
+                stream.carry = 0;
+            }
+        }
+
+        self.invalidate_efficiencies();
+        Ok(())
+    }
+
+    /// Throws away every efficiency cached in this factory. Needed after changing anything [`Stream::efficiency`] depends on
+    /// without going through [`Stream::set_mult`], [`Stream::set_inputs`] or [`Factory::set_recipe_period`]
+    pub fn invalidate_efficiencies(&self) {
+        self.generation.bump();
+    }
+
+    /// Outputs of named streams that no other stream consumes, sorted by stream name
+    pub fn final_outputs(&self) -> Vec<(String, Product)> {
+        let mut names: Vec<&String> = self.streams.keys().collect();
+        names.sort();
+
+        let mut out = Vec::new();
+
+        for name in names {
+            let stream = &self.streams[name];
+
+            for output in stream.borrow().recipe.borrow().outputs.iter().filter(|output| !output.voided) {
+                let product = *output.product.borrow();
+
+                if self.consumers_of(stream, &product).is_empty() && !out.contains(&(name.to_owned(), product)) {
+                    out.push((name.to_owned(), product));
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Efficiency of `stream` under the factory's efficiency model
+    pub fn efficiency_of(&self, stream: &Stream) -> Efficiency {
+        stream.efficiency_with(self.efficiency_model)
+    }
+
+    /// Every stream reachable from a named stream, including unnamed ones created by nested calls
+    pub fn all_streams(&self) -> Vec<Rc<RefCell<Stream>>> {
+        let mut out: Vec<Rc<RefCell<Stream>>> = Vec::with_capacity(self.streams.len());
+        let mut stack: Vec<Rc<RefCell<Stream>>> = self.streams.values().cloned().collect();
+
+        while let Some(stream) = stack.pop() {
+            if out.iter().any(|seen| Rc::ptr_eq(seen, &stream)) {
+                continue;
+            }
+
+            stack.extend(stream.borrow().inputs().inner.iter().map(|(_, input, _)| input.clone()));
+            out.push(stream);
+        }
+
+        out
+    }
+
+    /// Streams that take `product` as an input from `stream`
+    pub fn consumers_of(&self, stream: &Rc<RefCell<Stream>>, product: &Product) -> Vec<Rc<RefCell<Stream>>> {
+        self.all_streams().into_iter().filter(|consumer| {
+            consumer.borrow().inputs().inner.iter().any(|(input_product, input, _)| {
+                &*input_product.borrow() == product && Rc::ptr_eq(input, stream)
+            })
+        }).collect()
+    }
+
+    /// Optimal per-tick consumption of every product summed over all streams, whether or not it's produced internally
+    pub fn total_demand(&self) -> HashMap<Product, Rate> {
+        let mut demand: HashMap<Product, Rate> = HashMap::new();
+
+        for stream in self.all_streams() {
+            let stream = stream.borrow();
+
+            let products: HashSet<Product> = stream.recipe.borrow().inputs.iter().map(|part| *part.product.borrow()).collect();
+
+            for product in products {
+                if let Some(rate) = stream.optimal_inflow_of(&product) {
+                    *demand.entry(product).or_insert(Rate::ZERO) += rate;
+                }
+            }
+        }
+
+        demand
+    }
+
+    /// Current per-tick production of every product summed over all streams
+    pub fn total_supply(&self) -> HashMap<Product, Rate> {
+        let mut supply: HashMap<Product, Rate> = HashMap::new();
+
+        for stream in self.all_streams() {
+            let stream = stream.borrow();
+
+            let products: HashSet<Product> = stream.recipe.borrow().outputs.iter().map(|part| *part.product.borrow()).collect();
+
+            for product in products {
+                if let Some(rate) = stream.rate_of(&product) {
+                    *supply.entry(product).or_insert(Rate::ZERO) += rate;
+                }
+            }
+        }
+
+        supply
+    }
+
+    /// Current per-tick production of `product` summed over all streams, zero if nothing makes it
+    pub fn total_output_of(&self, product: Product) -> Rate {
+        self.all_streams().iter()
+            .filter_map(|stream| stream.borrow().rate_of(&product))
+            .sum()
+    }
+
+    /// Current per-tick consumption of `product` summed over all streams, scaled by each stream's efficiency
+    pub fn total_input_of(&self, product: Product) -> Rate {
+        self.all_streams().iter()
+            .filter_map(|stream| {
+                let stream = stream.borrow();
+                Some(stream.optimal_inflow_of(&product)? * self.efficiency_of(&stream))
+            })
+            .sum()
+    }
+
+    /// Names of named streams that output `product`, sorted
+    pub fn producers_of(&self, product: &Product) -> Vec<String> {
+        let mut names: Vec<String> = self.streams.iter()
+            .filter(|(_, stream)| stream.borrow().recipe.borrow().optimal_outflow_of(product).is_some())
+            .map(|(name, _)| name.to_owned())
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    /// Names of every registered recipe that outputs `product`, whether or not a stream runs it, sorted
+    pub fn recipes_producing(&self, product: &Product) -> Vec<String> {
+        let mut names: Vec<String> = self.recipes.iter()
+            .filter(|(_, recipe)| recipe.borrow().outputs.iter().any(|output| &*output.product.borrow() == product))
+            .map(|(name, _)| name.to_owned())
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    /// Names of named streams running `recipe_name` or downstream of a stream that does, sorted.
+    /// Everything listed could be unbalanced by changing the recipe
+    pub fn recipe_dependents(&self, recipe_name: &str) -> Vec<String> {
+        let Some(recipe) = self.find_recipe("factory", recipe_name) else {
+            return Vec::new();
+        };
+
+        let all_streams = self.all_streams();
+        let mut affected: Vec<Rc<RefCell<Stream>>> = all_streams.iter()
+            .filter(|stream| Rc::ptr_eq(&stream.borrow().recipe, recipe))
+            .cloned()
+            .collect();
+        let mut idx = 0;
+
+        while idx < affected.len() {
+            let stream = affected[idx].clone();
+            idx += 1;
+
+            for consumer in &all_streams {
+                let consumes = consumer.borrow().inputs().inner.iter().any(|(_, input, _)| Rc::ptr_eq(input, &stream));
+
+                if consumes && !affected.iter().any(|seen| Rc::ptr_eq(seen, consumer)) {
+                    affected.push(consumer.clone());
+                }
+            }
+        }
+
+        let mut names: Vec<String> = self.streams.iter()
+            .filter(|(_, stream)| affected.iter().any(|seen| Rc::ptr_eq(seen, stream)))
+            .map(|(name, _)| name.to_owned())
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    /// Names of named streams tagged with `tag`, sorted
+    pub fn streams_with_tag(&self, tag: &str) -> Vec<String> {
+        let mut names: Vec<String> = self.streams.iter()
+            .filter(|(_, stream)| stream.borrow().tags.contains(tag))
+            .map(|(name, _)| name.to_owned())
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    /// Names of recipes tagged with `tag`, sorted
+    pub fn recipes_with_tag(&self, tag: &str) -> Vec<String> {
+        let mut names: Vec<String> = self.recipes.iter()
+            .filter(|(_, recipe)| recipe.borrow().tags.contains(tag))
+            .map(|(name, _)| name.to_owned())
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    /// Total number of logical machines across every stream, counting a fractional mult as the whole machines it runs on
+    pub fn total_machines(&self) -> usize {
+        self.all_streams().iter().map(|stream| stream.borrow().machines()).sum()
+    }
+
+    /// Efficiency the stream `name` should reach: its own objective if it has one, or the factory's `target_efficiency`
+    pub fn target_of(&self, name: &str) -> Efficiency {
+        self.stream_targets.get(name).copied().unwrap_or(self.target_efficiency)
+    }
+
+    /// Named streams running below their [`Factory::target_of`], least efficient first
+    pub fn underperforming(&self) -> Vec<(String, Efficiency)> {
+        let mut out: Vec<(String, Efficiency)> = self.streams.iter()
+            .map(|(name, stream)| (name.to_owned(), self.efficiency_of(&stream.borrow())))
+            .filter(|(name, efficiency)| *efficiency < self.target_of(name))
+            .collect();
+
+        out.sort_by(|lhs, rhs| lhs.1.total_cmp(&rhs.1).then_with(|| lhs.0.cmp(&rhs.0)));
+        out
+    }
+
+    /// Least efficient named stream that takes inputs, since sources always run at full efficiency.
+    /// Ties go to the stream feeding the most other streams, then to the first name
+    pub fn bottleneck(&self) -> Option<(String, Efficiency)> {
+        self.streams.iter()
+            .filter(|(_, stream)| !stream.borrow().inputs().inner.is_empty())
+            .map(|(name, stream)| {
+                let mut consumers: Vec<Rc<RefCell<Stream>>> = Vec::new();
+
+                for output in stream.borrow().recipe.borrow().outputs.iter() {
+                    for consumer in self.consumers_of(stream, &output.product.borrow()) {
+                        if !consumers.iter().any(|seen| Rc::ptr_eq(seen, &consumer)) {
+                            consumers.push(consumer);
+                        }
+                    }
+                }
+
+                (name, self.efficiency_of(&stream.borrow()), consumers.len())
+            })
+            .min_by(|lhs, rhs| lhs.1.total_cmp(&rhs.1).then_with(|| rhs.2.cmp(&lhs.2)).then_with(|| lhs.0.cmp(rhs.0)))
+            .map(|(name, efficiency, _)| (name.to_owned(), efficiency))
+    }
+
+    /// Buildings needed for each recipe across every stream, sorted by recipe name.
+    /// Streams running recipes that were never registered are grouped under `<unnamed>`
+    pub fn machine_bill(&self) -> Vec<(String, usize)> {
+        let mut bill: Vec<(String, usize)> = Vec::new();
+
+        for stream in self.all_streams() {
+            let stream = stream.borrow();
+            let name = self.recipe_name(&stream.recipe).unwrap_or_else(|| "<unnamed>".to_owned());
+
+            match bill.iter_mut().find(|(recipe, _)| *recipe == name) {
+                Some((_, buildings)) => *buildings += stream.buildings(),
+                None => bill.push((name, stream.buildings())),
+            }
+        }
+
+        bill.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+        bill
+    }
+
+    /// Power drawn by every stream, with each machine drawing its recipe's full power even when it's starved of inputs.
+    /// Recipes without a power count as zero
+    pub fn total_power(&self) -> f64 {
+        self.all_streams().iter()
+            .map(|stream| {
+                let stream = stream.borrow();
+                let power = stream.recipe.borrow().power.unwrap_or(0.0);
+                power * stream.mult().to_f64()
+            })
+            .sum()
+    }
+
+    /// Total number of physical buildings across every stream
+    pub fn total_buildings(&self) -> usize {
+        self.all_streams().iter().map(|stream| stream.borrow().buildings()).sum()
+    }
+
+    pub fn tick(&mut self, ticks: usize) {
+        let report = self.tick_report(ticks);
+
+        println!();
+        println!("[-- Tick {} --]", report.tick);
+
+        for event in &report.events {
+            println!("[-- Tick {} --] {}", report.tick, self.event_text(event));
+        }
+    }
+
+    /// Advances the simulation by `ticks`, returning its events along with how much each named stream made and holds
+    pub fn tick_report(&mut self, ticks: usize) -> TickReport {
+        let events = self.tick_events(ticks);
+        let mut names: Vec<&String> = self.streams.keys().collect();
+        names.sort();
+
+        let mut records = Vec::new();
+
+        for name in names {
+            let stream = self.streams[name].borrow();
+            let mut buffered: Vec<(&Product, &Buffer)> = stream.buffers.iter().collect();
+            buffered.sort_by_key(|(product, _)| product.id);
+
+            for (product, buffer) in buffered {
+                let produced = events.iter().map(|event| match event {
+                    TickEvent::Produced { stream, product: made, amount } if stream == name && made == product => *amount,
+                    _ => 0,
+                }).sum();
+
+                records.push(TickRecord {
+                    name: name.to_owned(),
+                    product: *product,
+                    produced,
+                    buffer_current: buffer.current,
+                    buffer_max: buffer.max,
+                });
+            }
+        }
+
+        TickReport { tick: self.tick, events, records }
+    }
+
+    /// Advances the simulation by `ticks` one at a time, sampling every named stream's buffers after each.
+    /// With `max_samples`, only that many of the latest samples are kept
+    pub fn tick_trace(&mut self, ticks: usize, max_samples: Option<usize>) -> TickTrace {
+        let mut names: Vec<String> = self.streams.keys().cloned().collect();
+        names.sort();
+
+        let mut buffers = Vec::new();
+
+        for name in names {
+            let mut products: Vec<Product> = self.streams[&name].borrow().buffers.keys().copied().collect();
+            products.sort_by_key(|product| product.id);
+            buffers.extend(products.into_iter().map(|product| (name.to_owned(), product)));
+        }
+
+        let cap = max_samples.unwrap_or(ticks);
+        let mut sampled_ticks = VecDeque::with_capacity(cap.min(ticks));
+        let mut samples = vec![VecDeque::with_capacity(cap.min(ticks)); buffers.len()];
+
+        for _ in 0..ticks {
+            self.tick_streaming(1, |_| {});
+
+            if cap == 0 {
+                continue;
+            }
+
+            if sampled_ticks.len() == cap {
+                sampled_ticks.pop_front();
+                samples.iter_mut().for_each(|samples| { samples.pop_front(); });
+            }
+
+            sampled_ticks.push_back(self.tick);
+
+            for ((name, product), samples) in buffers.iter().zip(&mut samples) {
+                samples.push_back(self.streams[name].borrow().buffers[product].current);
+            }
+        }
+
+        TickTrace {
+            ticks: sampled_ticks.into(),
+            buffers: buffers.into_iter().zip(samples)
+                .map(|((name, product), samples)| BufferTrace { name, product, samples: samples.into() })
+                .collect(),
+        }
+    }
+
+    pub fn event_text(&self, event: &TickEvent) -> String {
+        match event {
+            TickEvent::Produced { product, amount, .. } => format!("Produced {} x{amount}", self.product_label(*product)),
+            TickEvent::Learned { knowledge, amount, progress } => format!("Learned {knowledge} x{amount} ({progress})"),
+            TickEvent::Transfer { from_stream, to_stream, product, amount } => {
+                format!("Moved {} x{amount} from {from_stream} to {to_stream}", self.product_label(*product))
+            },
+            TickEvent::Depleted { stream, product } => format!("{stream} ran out of reserved {}", self.product_label(*product)),
+            TickEvent::Discarded { stream, product, amount } => {
+                format!("Discarded {} x{amount} from {stream}", self.product_label(*product))
+            },
+            TickEvent::Stalled { stream, product } => format!("{stream} stalled, its {} buffer is full", self.product_label(*product)),
+        }
+    }
+
+    /// Name of `stream` if it was registered, or `<unnamed>` for streams created by nested calls
+    pub fn stream_name(&self, stream: &Rc<RefCell<Stream>>) -> String {
+        self.streams.iter()
+            .find(|(_, named)| Rc::ptr_eq(named, stream))
+            .map(|(name, _)| name.to_owned())
+            .unwrap_or_else(|| "<unnamed>".to_owned())
+    }
+
+    /// Name `recipe` was registered under, if any
+    pub fn recipe_name(&self, recipe: &Rc<RefCell<Recipe>>) -> Option<String> {
+        self.recipes.iter()
+            .find(|(_, named)| Rc::ptr_eq(named, recipe))
+            .map(|(name, _)| name.to_owned())
+    }
+
+    /// Name `product` was registered under, if any
+    pub fn product_name(&self, product: Product) -> Option<&str> {
+        self.product_names.get(&product).map(String::as_str)
+    }
+
+    /// Every defined product as `(name, product)` sorted by name, leaving out the `__next` counter.
+    /// Names are as declared, without the prefix products from mods other than base are stored under
+    pub fn products(&self) -> impl Iterator<Item = (&str, &Rc<RefCell<Product>>)> {
+        Self::sorted_entries(&self.products, false)
+    }
+
+    /// Like [`Self::products`], but named by the keys they're stored under, like `__MOD::name`
+    pub fn qualified_products(&self) -> impl Iterator<Item = (&str, &Rc<RefCell<Product>>)> {
+        Self::sorted_entries(&self.products, true)
+    }
+
+    /// Every defined recipe as `(name, recipe)` sorted by name, with names as declared
+    pub fn recipes(&self) -> impl Iterator<Item = (&str, &Rc<RefCell<Recipe>>)> {
+        Self::sorted_entries(&self.recipes, false)
+    }
+
+    /// Like [`Self::recipes`], but named by the keys they're stored under, like `__MOD::name`
+    pub fn qualified_recipes(&self) -> impl Iterator<Item = (&str, &Rc<RefCell<Recipe>>)> {
+        Self::sorted_entries(&self.recipes, true)
+    }
+
+    fn sorted_entries<T>(map: &HashMap<String, Rc<RefCell<T>>>, qualified: bool) -> impl Iterator<Item = (&str, &Rc<RefCell<T>>)> {
+        let mut entries: Vec<_> = map.iter()
+            .filter(|(key, _)| *key != "__next")
+            .map(|(key, value)| {
+                let name = if qualified { key.as_str() } else { Self::unqualified_name(key) };
+                (name, key, value)
+            })
+            .collect();
+
+        // the same name can be declared by several modules, so ties fall back to the full key to keep the order stable
+        entries.sort_by(|lhs, rhs| (lhs.0, lhs.1).cmp(&(rhs.0, rhs.1)));
+        entries.into_iter().map(|(name, _, value)| (name, value))
+    }
+
+    /// `key` without the module prefix added by [`Self::qualified_name`] and [`Self::qualified_stream_name`]
+    pub fn unqualified_name(key: &str) -> &str {
+        match key.strip_prefix("__").and_then(|rest| rest.split_once("::")) {
+            Some((_, name)) => name,
+            None => key,
+        }
+    }
+
+    /// Name of `product` for printing, which is `<unknown product N>` if it was never registered
+    pub fn product_label(&self, product: Product) -> String {
+        self.product_name(product).map(str::to_owned).unwrap_or_else(|| format!("<unknown product {}>", product.id))
+    }
+
+    /// Human-readable rendering of `value`, with products by name, recipes by what they turn into what,
+    /// and streams with their mult and efficiency
+    pub fn describe(&self, value: &Value) -> String {
+        let parts = |parts: &[RecipePart]| parts.iter()
+            .map(|part| format!("{} * {}", self.product_label(*part.product.borrow()), part.amount))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        match value {
+            Value::Product(_, product) => self.product_label(*product.borrow()),
+            Value::Recipe(name, recipe) => {
+                let recipe = recipe.borrow();
+                format!("{name}({}) -> ({}) / {}", parts(&recipe.inputs), parts(&recipe.outputs), recipe.rate.period())
+            },
+            Value::Stream(name, stream) => {
+                let stream = stream.borrow();
+                format!("{name} x{} @ {:.0}%", stream.mult(), self.efficiency_of(&stream) * 100.0)
+            },
+            Value::RecipePart(part) => format!("{} * {}", self.product_label(*part.product.borrow()), part.amount),
+            Value::Rate(rate) => rate.to_string(),
+            Value::Tapped(tapped) => {
+                let outputs = tapped.source.borrow().recipe.borrow().outputs.iter()
+                    .map(|output| *output.product.borrow())
+                    .map(|product| format!("{} @ {}", self.product_label(product), tapped.rate_of(&product).unwrap()))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                format!("{} (tapped) -> {outputs}", tapped.name)
+            },
+            Value::List(values) => format!("[{}]", values.iter().map(|value| self.describe(value)).collect::<Vec<_>>().join(", ")),
+            value => value.to_string(),
+        }
+    }
+
+    /// Advances the simulation by `ticks`, returning what happened in order.
+    /// Transfers between buffers are only recorded when `verbose` is set
+    pub fn tick_events(&mut self, ticks: usize) -> Vec<TickEvent> {
+        let mut events = Vec::new();
+        self.tick_streaming(ticks, |event| events.push(event));
+
+        events
+    }
+
+    /// Advances the simulation to the next time any stream finishes a cycle, returning how many ticks passed and what happened.
+    /// Streams waiting on inputs are treated as starting a fresh cycle, the same way `tick` does
+    pub fn tick_to_next_event(&mut self) -> (usize, Vec<TickEvent>) {
+        let Some(elapsed) = self.streams.values().map(|stream| {
+            let stream = stream.borrow();
+            stream.next.unwrap_or(stream.ticks).max(1)
+        }).min() else {
+            return (0, Vec::new());
+        };
+
+        (elapsed, self.tick_events(elapsed))
+    }
+
+    /// Advances the simulation by `ticks`, handing each event to `callback` as it happens instead of collecting them.
+    /// Produces the same events in the same order as [`Factory::tick_events`]
+    pub fn tick_streaming(&mut self, ticks: usize, mut callback: impl FnMut(TickEvent)) {
+        self.tick += ticks;
+
+        for (name, stream) in self.streams.iter() {
+            {
+                // don't run streams that only output unlocked knowledge with no products, or that would output locked knowledge
+                let stream = stream.borrow();
+                let recipe = stream.recipe.borrow();
+                if ((recipe.knowledge.iter().any(|knowledge| !knowledge.0.borrow().unlockable())
+                    || recipe.knowledge.iter().all(|knowledge| knowledge.0.borrow().unlocked)
+                ) && recipe.outputs.len() == 0) || !recipe.unlocked {
+                    continue;
+                }
+            }
+
+            // 1. tick stream if in progress
+            // 2. fill output buffers if stream finishes
+            // 2a. fill input buffers
+            // 2b. start stream again if inputs satisfied
+            // 3. repeat as many times as can fit within <ticks> ticks
+
+            let mut ticks = ticks;
+            let mut cycles = 0;
+            let waiting = stream.borrow().next;
+
+            // a stalled stream stays stalled until something draws its blocking buffer down
+            if waiting.is_none() && stream.borrow().blocking_output().is_some() {
+                continue;
+            }
+
+            let mut next = waiting.unwrap_or_else(|| stream.borrow_mut().cycle_ticks());
+
+            // cycles of fractional periods differ in length, so they're counted one at a time
+            while ticks >= next {
+                ticks -= next;
+                cycles += 1;
+                next = stream.borrow_mut().cycle_ticks();
+            }
+
+            next -= ticks;
+            stream.borrow_mut().next = Some(next);
+
+            let outputs = stream.borrow().recipe.borrow().outputs.clone();
+            let knowledge = stream.borrow().recipe.borrow().knowledge.clone();
+            let mut produced: Vec<RecipePart> = outputs.iter().map(|output| RecipePart { product: output.product.clone(), amount: 0, voided: output.voided }).collect();
+            let machines = stream.borrow().machines();
+            let stocked = reserves_stocked(stream);
+            let mut discarded: HashMap<Product, usize> = HashMap::new();
+
+            for _ in 0..cycles {
+                let totals_before = self.check_conservation.then(|| buffered_totals(stream));
+                let mut cycle_discarded: HashMap<Product, usize> = HashMap::new();
+
+                for output in outputs.iter().filter(|output| !output.voided) {
+                    let mut mut_stream = stream.borrow_mut();
+                    let product = *output.product.borrow();
+                    let existing = mut_stream.buffers.get_mut(&product).unwrap();
+                    *cycle_discarded.entry(product).or_insert(0) += existing.accept(output.amount * machines);
+                }
+    
+                for (knowledge, amount) in &knowledge {
+                    if knowledge.borrow().unlockable() {
+                        knowledge.borrow_mut().progress_by(amount * machines);
+                    }
+                }
+
+                for (idx, output) in outputs.iter().enumerate() {
+                    if produced[idx].product == output.product {
+                        produced[idx].amount += output.amount;
+                    } else {
+                        produced.iter_mut().find(|produced| produced.product == output.product).unwrap().amount += output.amount;
+                    }
+                }
+
+                for (knowledge, amount) in knowledge.iter() {
+                    let knowledge = knowledge.borrow();
+                    callback(TickEvent::Learned { knowledge: knowledge.name.clone(), amount: amount * machines, progress: knowledge.progress });
+                }
+
+                let inputs = stream.borrow().inputs().clone();
+
+                for (product, input, _) in inputs.inner {
+                    if let Some(buffer) = input.borrow_mut().buffers.get_mut(&*product.borrow()) {
+                        let mut own_buffer = stream.borrow().buffers.get(&*product.borrow()).cloned().unwrap_or_else(|| {
+                            let max = stream.borrow().recipe.borrow().required_of(&product.borrow()).unwrap() * self.profile.buffer_mult * machines;
+
+                            Buffer { current: 0, min: 0, max, reserve: false, policy: BufferPolicy::Block }
+                        });
+
+                        if own_buffer.reserve {
+                            continue;
+                        }
+
+                        let before = own_buffer.current;
+                        *cycle_discarded.entry(*product.borrow()).or_insert(0) += own_buffer.fill_from(buffer);
+                        stream.borrow_mut().buffers.insert(*product.borrow(), own_buffer);
+
+                        if self.verbose && own_buffer.current > before {
+                            callback(TickEvent::Transfer {
+                                from_stream: self.stream_name(&input),
+                                to_stream: name.to_owned(),
+                                product: *product.borrow(),
+                                amount: own_buffer.current - before,
+                            });
+                        }
+                    }
+                }
+
+                let started = stream.borrow_mut().try_start_produce();
+
+                if let Some(before) = totals_before {
+                    let mut expected = before;
+
+                    for output in outputs.iter().filter(|output| !output.voided) {
+                        *expected.entry(*output.product.borrow()).or_insert(0) += (output.amount * machines) as isize;
+                    }
+
+                    for (product, amount) in &cycle_discarded {
+                        *expected.entry(*product).or_insert(0) -= *amount as isize;
+                    }
+
+                    if started {
+                        for input in &stream.borrow().recipe.borrow().inputs {
+                            *expected.entry(*input.product.borrow()).or_insert(0) -= (input.amount * machines) as isize;
+                        }
+                    }
+
+                    let after = buffered_totals(stream);
+
+                    for (product, expected) in expected {
+                        let actual = after.get(&product).copied().unwrap_or(0);
+
+                        if actual != expected {
+                            panic!(
+                                "[-- Tick {} --] {} of {} went missing in {name} (expected {expected}, found {actual})",
+                                self.tick, expected - actual, self.product_label(product),
+                            );
+                        }
+                    }
+                }
+
+                for (product, amount) in cycle_discarded {
+                    *discarded.entry(product).or_insert(0) += amount;
+                }
+
+                if !started {
+                    // can't produce another batch
+                    stream.borrow_mut().next = None;
+
+                    let blocking = stream.borrow().blocking_output();
+
+                    if let Some(product) = blocking {
+                        callback(TickEvent::Stalled { stream: name.to_owned(), product });
+                    }
+
+                    break;
+                }
+            }
+
+            for output in produced {
+                if output.amount > 0 {
+                    callback(TickEvent::Produced { stream: name.to_owned(), product: *output.product.borrow(), amount: output.amount * machines });
+                }
+            }
+
+            let mut discarded: Vec<(Product, usize)> = discarded.into_iter().filter(|(_, amount)| *amount > 0).collect();
+            discarded.sort_by_key(|(product, _)| product.id);
+
+            for (product, amount) in discarded {
+                callback(TickEvent::Discarded { stream: name.to_owned(), product, amount });
+            }
+
+            let still_stocked = reserves_stocked(stream);
+
+            for product in stocked.into_iter().filter(|product| !still_stocked.contains(product)) {
+                callback(TickEvent::Depleted { stream: name.to_owned(), product });
+            }
+        }
+    }
+}
+
+/// `roots` and every stream they draw from, ordered so that each one comes before all of the streams it takes inputs from
+fn consumers_first<'a>(roots: impl IntoIterator<Item = &'a Rc<RefCell<Stream>>>) -> Vec<Rc<RefCell<Stream>>> {
+    fn visit(stream: &Rc<RefCell<Stream>>, visited: &mut Vec<Rc<RefCell<Stream>>>) {
+        if visited.iter().any(|seen| Rc::ptr_eq(seen, stream)) {
+            return;
+        }
+
+        for (_, input, _) in &stream.borrow().inputs().inner {
+            visit(input, visited);
+        }
+
+        visited.push(stream.clone());
+    }
+
+    let mut order = Vec::new();
+
+    for root in roots {
+        visit(root, &mut order);
+    }
+
+    order.reverse();
+    order
+}
+
+/// Reserve buffers on `stream` holding enough for at least one more cycle
+fn reserves_stocked(stream: &Rc<RefCell<Stream>>) -> Vec<Product> {
+    let stream = stream.borrow();
+    let recipe = stream.recipe.borrow();
+
+    stream.buffers.iter()
+        .filter(|(product, buffer)| buffer.reserve && recipe.required_of(product).is_some_and(|required| buffer.available() >= required * stream.machines()))
+        .map(|(product, _)| *product)
+        .collect()
+}
+
+/// Items held in each product's buffers on `stream` and the streams feeding it
+fn buffered_totals(stream: &Rc<RefCell<Stream>>) -> HashMap<Product, isize> {
+    let mut totals = HashMap::new();
+    let mut counted: Vec<Rc<RefCell<Stream>>> = vec![stream.clone()];
+
+    for (_, input, _) in &stream.borrow().inputs().inner {
+        if !counted.iter().any(|seen| Rc::ptr_eq(seen, input)) {
+            counted.push(input.clone());
+        }
+    }
+
+    for stream in counted {
+        for (product, buffer) in &stream.borrow().buffers {
+            *totals.entry(*product).or_insert(0) += buffer.current as isize;
+        }
+    }
+
+    totals
+}
+
+/// Tags as a sorted list of strings
+fn tags_to_value(tags: &HashSet<String>) -> Value {
+    let mut tags: Vec<&String> = tags.iter().collect();
+    tags.sort();
+
+    Value::List(tags.into_iter().map(|tag| Value::String(tag.to_owned())).collect())
+}
+
+fn tags_from_values(values: Vec<Value>) -> HashSet<String> {
+    values.into_iter().filter_map(|value| {
+        if let Value::String(tag) = value {
+            Some(tag)
+        } else {
+            None
+        }
+    }).collect()
+}
+
+impl Value {
+    /// The method or attribute `rhs` names on this value, erroring if there's no such member
+    pub fn access(&self, rhs: &str) -> Result<Value, FactoryError> {
+        match self {
+            Self::Stream(..) => {
                 match rhs {
                     "buffer"
+                    | "reserve"
+                    | "allow"
                     | "solve"
-                    | "log" => Value::Method(Box::new(Method { object: self.clone(), name: rhs.to_owned() })),
-                    _ => unimplemented!(),
+                    | "log"
+                    | "surplus"
+                    | "void"
+                    | "policy"
+                    | "scale_to"
+                    | "with_mult"
+                    | "rate"
+                    | "efficiency" => Ok(Value::Method(Box::new(Method { object: self.clone(), name: rhs.to_owned() }))),
+                    "tags"
+                    | "recipe" => Ok(Value::Attribute(Box::new(Attribute { object: self.clone(), name: rhs.to_owned() }))),
+                    _ => Err(FactoryError::Undefined(rhs.to_owned())),
                 }
             }
             Self::Knowledge(..) => {
                 match rhs {
                     "threshold"
-                    | "deps" => Value::Attribute(Box::new(Attribute { object: self.clone(), name: rhs.to_owned() })),
-                    _ => unimplemented!(),
+                    | "deps" => Ok(Value::Attribute(Box::new(Attribute { object: self.clone(), name: rhs.to_owned() }))),
+                    _ => Err(FactoryError::Undefined(rhs.to_owned())),
                 }
             },
-            _ => unimplemented!(),
+            Self::Recipe(..) => {
+                match rhs {
+                    "buildings"
+                    | "tags"
+                    | "machine" => Ok(Value::Attribute(Box::new(Attribute { object: self.clone(), name: rhs.to_owned() }))),
+                    "period"
+                    | "ratio" => Ok(Value::Method(Box::new(Method { object: self.clone(), name: rhs.to_owned() }))),
+                    _ => Err(FactoryError::Undefined(rhs.to_owned())),
+                }
+            },
+            Self::Tapped(..) => {
+                match rhs {
+                    "log"
+                    | "rate" => Ok(Value::Method(Box::new(Method { object: self.clone(), name: rhs.to_owned() }))),
+                    _ => Err(FactoryError::Undefined(rhs.to_owned())),
+                }
+            },
+            _ => Err(FactoryError::TypeError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(factory: &mut Factory, module: &str, src: &str) -> Result<(), FactoryError> {
+        factory.add_module(module, lang::parse_tokens(lang::tokenize(src).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn same_name_in_two_modules() {
+        let mut factory = Factory::new();
+        load(&mut factory, "base", "product iron; recipe Iron() -> iron * 2 / 1000ms;").unwrap();
+        load(&mut factory, "foo", "product iron; recipe Iron() -> iron / 500ms;").unwrap();
+
+        let base_iron = *factory.find_product("bar", "iron").unwrap().borrow();
+        let foo_iron = *factory.find_product("foo", "iron").unwrap().borrow();
+        assert_ne!(base_iron, foo_iron);
+
+        let foo_recipe = factory.find_recipe("foo", "Iron").unwrap();
+        assert_eq!(*foo_recipe.borrow().outputs[0].product.borrow(), foo_iron);
+        assert!(!Rc::ptr_eq(foo_recipe, factory.find_recipe("bar", "Iron").unwrap()));
+
+        // only a second `iron` in the same module clashes
+        assert!(matches!(load(&mut factory, "foo", "product iron;"), Err(FactoryError::Exists(_))));
+
+        // methods acting on the factory see its own recipe before the base mod's
+        load(&mut factory, "factory", "product iron; recipe Iron() -> iron / 250ms;").unwrap();
+        let stream = factory.add_stream("iron", "Iron", Vec::new(), 1).unwrap();
+        assert!(Rc::ptr_eq(&stream.borrow().recipe, &factory.recipes["__FACTORY::Iron"]));
+    }
+
+    #[test]
+    fn builtins_are_called_and_compared_by_name() {
+        let mut factory = Factory::new();
+        let call = Expr::Call {
+            lhs: Box::new(Expr::Ident("min".to_owned())),
+            args: vec![Expr::Literal(Literal::Int(3)), Expr::Literal(Literal::Int(2))],
+        };
+
+        assert_eq!(factory.process_expr(call, "factory").unwrap(), Some(Value::Int(2)));
+        assert_eq!(Value::Builtin("min".to_owned()), Value::Builtin("min".to_owned()));
+        assert_ne!(Value::Builtin("min".to_owned()), Value::Builtin("max".to_owned()));
+    }
+
+    fn call_of(factory: &Factory, recipe: &str, args: Vec<Value>) -> Value {
+        Value::Call(Box::new(Value::Recipe(recipe.to_owned(), factory.recipes[recipe].clone())), args)
+    }
+
+    #[test]
+    fn no_input_recipe_only_gets_output_buffers() {
+        let mut factory = Factory::new();
+        load(&mut factory, "base", "product iron; product gear; recipe Iron() -> iron * 2 / 1000ms; recipe Gear(iron * 4) -> gear / 2000ms;").unwrap();
+        let iron = *factory.products["iron"].borrow();
+
+        let stream = factory.parse_call(call_of(&factory, "Iron", Vec::new())).unwrap();
+        let stream = stream.borrow();
+
+        assert!(*stream.inputs() == InputStreams::NONE);
+        assert_eq!(stream.buffers.len(), 1);
+        assert_eq!(stream.buffers[&iron].max, 2 * factory.profile.buffer_mult);
+        assert_eq!(stream.efficiency(), 1.0);
+    }
+
+    #[test]
+    fn argument_count_must_match_inputs() {
+        let mut factory = Factory::new();
+        load(&mut factory, "base", "product iron; product gear; recipe Iron() -> iron * 2 / 1000ms; recipe Gear(iron * 4) -> gear / 2000ms;").unwrap();
+        let iron = factory.parse_call(call_of(&factory, "Iron", Vec::new())).unwrap();
+
+        let extra = call_of(&factory, "Iron", vec![Value::Stream("iron".to_owned(), iron)]);
+        assert!(matches!(factory.parse_call(extra), Err(FactoryError::InvalidArguments)));
+
+        let missing = call_of(&factory, "Gear", Vec::new());
+        assert!(matches!(factory.parse_call(missing), Err(FactoryError::InvalidArguments)));
+    }
+
+    #[test]
+    fn shared_producer_is_sized_for_all_consumers_at_once() {
+        // each gear stream wants 0.4 of an iron machine, so the three together need 1.2, rounded up once to 2
+        let mut factory = FactoryBuilder::new()
+            .iron_and_gear(4, 5000)
+            .stream("iron", "Iron", &[], 1)
+            .stream("gear1", "Gear", &["iron"], 1)
+            .stream("gear2", "Gear", &["iron"], 1)
+            .stream("gear3", "Gear", &["iron"], 1)
+            .build()
+            .unwrap();
+
+        let gear = factory.streams["gear1"].clone();
+        factory.solve(gear);
+
+        assert_eq!(factory.streams["iron"].borrow().mult(), Mult::from(2));
+        assert!(factory.streams.values().all(|stream| stream.borrow().efficiency() == 1.0));
+    }
+
+    #[test]
+    fn machine_budget_is_never_exceeded() {
+        // a's share is 2.5 furnaces, but b and c each need one of their own, which leaves a with just one
+        let mut factory = FactoryBuilder::new()
+            .product("p_iron")
+            .recipe("Iron", &[], &[("p_iron", 2)], 1000)
+            .stream("a", "Iron", &[], 10)
+            .stream("b", "Iron", &[], 1)
+            .stream("c", "Iron", &[], 1)
+            .build()
+            .unwrap();
+        factory.recipes["Iron"].borrow_mut().machine = Some("furnace".to_owned());
+        let mults = |factory: &Factory| ["a", "b", "c"].map(|name| factory.streams[name].borrow().mult());
+
+        factory.machine_budgets.insert("furnace".to_owned(), 3);
+        factory.apply_machine_budgets().unwrap();
+        assert_eq!(mults(&factory), [Mult::from(1); 3]);
+
+        // three streams can't fit in two furnaces, so nothing changes
+        factory.machine_budgets.insert("furnace".to_owned(), 2);
+        assert!(matches!(factory.apply_machine_budgets(), Err(FactoryError::OverBudget(machine, 2)) if machine == "furnace"));
+        assert_eq!(mults(&factory), [Mult::from(1); 3]);
+    }
+
+    #[test]
+    fn supply_cap_below_one_machine() {
+        // one iron machine extracts 2 per 1000 ticks, twice the cap
+        let build = |profile| {
+            let mut factory = FactoryBuilder::from_factory(Factory::with_profile(profile))
+                .product("p_iron")
+                .recipe("Iron", &[], &[("p_iron", 2)], 1000)
+                .stream("iron", "Iron", &[], 1)
+                .build()
+                .unwrap();
+            let p_iron = *factory.products["p_iron"].borrow();
+            factory.raw_supply.insert(p_iron, Rate::new(1, 1000));
+            (factory, p_iron)
+        };
+
+        let (mut factory, _) = build(GameProfile::DEFAULT);
+        assert!(matches!(factory.apply_raw_supply(), Err(FactoryError::OverSupply(product)) if product == "p_iron"));
+        assert_eq!(factory.streams["iron"].borrow().mult(), Mult::from(1));
+
+        let (mut factory, p_iron) = build(GameProfile::SATISFACTORY);
+        factory.apply_raw_supply().unwrap();
+        assert_eq!(factory.streams["iron"].borrow().mult(), Mult::new(1, 2));
+        assert_eq!(factory.streams["iron"].borrow().rate_of(&p_iron), Some(Rate::new(1, 1000)));
+    }
+
+    #[test]
+    fn rounding_mode_sizes_shared_producer() {
+        // three gear streams want 1.2 iron machines between them
+        let solved = |rounding| {
+            let profile = GameProfile { rounding, ..GameProfile::DEFAULT };
+            let mut factory = FactoryBuilder::from_factory(Factory::with_profile(profile))
+                .iron_and_gear(4, 5000)
+                .stream("iron", "Iron", &[], 1)
+                .stream("gear1", "Gear", &["iron"], 1)
+                .stream("gear2", "Gear", &["iron"], 1)
+                .stream("gear3", "Gear", &["iron"], 1)
+                .build()
+                .unwrap();
+
+            factory.solve_all().unwrap();
+            let mult = factory.streams["iron"].borrow().mult();
+            mult
+        };
+
+        assert_eq!(solved(RoundingMode::Up), Mult::from(2));
+        assert_eq!(solved(RoundingMode::Nearest), Mult::from(1));
+        assert_eq!(solved(RoundingMode::Down), Mult::from(1));
+        assert_eq!(GameProfile::preset("factorio"), Some(GameProfile::FACTORIO));
+        assert_eq!("nearest".parse(), Ok(RoundingMode::Nearest));
+    }
+
+    #[test]
+    fn streams_below_their_target_underperform() {
+        // one iron machine only keeps gear at half speed
+        let mut factory = FactoryBuilder::new()
+            .iron_and_gear(4, 1000)
+            .stream("iron", "Iron", &[], 1)
+            .build()
+            .unwrap();
+
+        factory.eval_line("let gear = Gear(iron) @ 40%").unwrap();
+        assert_eq!(factory.target_of("gear"), 0.4);
+        assert!(factory.underperforming().is_empty());
+
+        // the factory-wide target only applies to streams without their own
+        factory.eval_line("targetEfficiency 60%").unwrap();
+        assert!(factory.underperforming().is_empty());
+
+        factory.stream_targets.clear();
+        assert_eq!(factory.underperforming(), [("gear".to_owned(), 0.5)]);
+    }
+
+    #[test]
+    fn streaming_ticks_match_collected_events() {
+        // a single named stream, so both runs tick streams in the same order. A small blocking buffer makes it stall too
+        let build = || {
+            let mut factory = FactoryBuilder::new()
+                .product("p_iron")
+                .recipe("Iron", &[], &[("p_iron", 2)], 3)
+                .stream("iron", "Iron", &[], 2)
+                .build()
+                .unwrap();
+            let iron = *factory.products["p_iron"].borrow();
+            factory.streams["iron"].borrow_mut().buffers.get_mut(&iron).unwrap().max = 20;
+            factory.verbose = true;
+            factory
+        };
+
+        let mut collected = build();
+        let mut streamed = build();
+        let mut events = Vec::new();
+
+        let expected: Vec<TickEvent> = [7, 1, 30].into_iter().flat_map(|ticks| collected.tick_events(ticks)).collect();
+
+        for ticks in [7, 1, 30] {
+            streamed.tick_streaming(ticks, |event| events.push(event));
+        }
+
+        assert!(expected.iter().any(|event| matches!(event, TickEvent::Stalled { .. })));
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn products_in_one_module_share_its_id() {
+        let mut factory = Factory::new();
+        factory.register_product("a", "foo").unwrap();
+        factory.register_product("b", "foo").unwrap();
+        factory.register_product("c", "bar").unwrap();
+
+        let module = |name: &str| factory.products[name].borrow().module;
+        assert_eq!(module("__FOO::a"), module("__FOO::b"));
+        assert_ne!(module("__FOO::a"), module("__BAR::c"));
+    }
+
+    #[test]
+    fn solving_scales_upstream_buffers_with_the_mult() {
+        // going from 3 iron machines to 5 used to keep the buffer as it was, since 5 / 3 truncated to 1
+        let mut factory = FactoryBuilder::new()
+            .iron_and_gear(5, 1000)
+            .stream("iron", "Iron", &[], 3)
+            .stream("gear", "Gear", &["iron"], 2)
+            .build()
+            .unwrap();
+
+        let iron = factory.streams["iron"].clone();
+        let p_iron = *factory.products["p_iron"].borrow();
+        let before = iron.borrow().buffers[&p_iron].max;
+
+        let gear = factory.streams["gear"].clone();
+        factory.solve(gear);
+
+        assert_eq!(iron.borrow().mult(), Mult::from(5));
+        assert_eq!(iron.borrow().buffers[&p_iron].max, before * 5 / 3);
+        assert_eq!(iron.borrow().buffers[&p_iron].max, 2 * factory.profile.buffer_mult * 5);
+    }
+
+    #[test]
+    fn fractional_period_carries_its_remainder() {
+        let mut factory = Factory::new();
+        load(&mut factory, "base", "product iron; recipe Fast() -> iron / 1.5;").unwrap();
+        factory.add_stream("fast", "Fast", Vec::new(), 1).unwrap();
+
+        // cycles alternate between 1 and 2 ticks, so 6 ticks fit 4 of them rather than the 6 or 3 truncating would give
+        let cycles: usize = factory.tick_events(6).into_iter().map(|event| match event {
+            TickEvent::Produced { amount, .. } => amount,
+            _ => 0,
+        }).sum();
+
+        assert_eq!(cycles, 4);
+    }
+
+    #[test]
+    fn recipes_can_use_products_declared_below_them() {
+        let mut factory = Factory::new();
+        load(&mut factory, "base", "recipe Iron() -> iron * 2 / 1000ms; product iron;").unwrap();
+        load(&mut factory, "factory", "recipe Plate(iron * 2) -> plate / 1000ms; product plate; let plates = Plate(Iron());").unwrap();
+
+        let plate = *factory.products["__FACTORY::plate"].borrow();
+        assert_eq!(*factory.recipes["__FACTORY::Plate"].borrow().outputs[0].product.borrow(), plate);
+        assert!(factory.streams.contains_key("plates"));
+
+        let mut factory = Factory::new();
+        let undefined = load(&mut factory, "base", "recipe Iron() -> iron * 2 / 1000ms; product copper;");
+        assert!(matches!(undefined, Err(FactoryError::Undefined(name)) if name == "iron"));
+    }
+
+    #[test]
+    fn introspection_hides_next() {
+        let mut factory = Factory::new();
+        load(&mut factory, "base", "product iron; product copper; recipe Iron() -> iron / 1000ms;").unwrap();
+        load(&mut factory, "foo", "product iron; recipe Iron() -> iron / 500ms;").unwrap();
+        assert!(factory.products.contains_key("__next"));
+
+        let products: Vec<&str> = factory.products().map(|(name, _)| name).collect();
+        assert_eq!(products, ["copper", "iron", "iron"]);
+
+        let names = factory.qualified_products().map(|(name, _)| name)
+            .chain(factory.recipes().map(|(name, _)| name))
+            .chain(factory.qualified_recipes().map(|(name, _)| name));
+
+        for name in names {
+            assert!(!name.contains("__next"), "{name}");
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,383 @@
+use std::{cell::RefCell, cmp::Ordering, collections::{HashMap, HashSet}, rc::Rc};
+
+use crate::{rate::Rate, Efficiency, Product, Recipe, RecipePart, Stream};
+
+use super::Factory;
+
+/// A stream reached searching upstream in [`Factory::chain_latency`],
+/// with the index of the consumer it was reached from and the product it supplies that consumer
+type Hop = (Rc<RefCell<Stream>>, Option<usize>, Option<Product>);
+
+/// What [`Factory::suggest_recipe`] tries to minimize per item of the product
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptMetric {
+    /// Raw items pulled into the chain, where raw products are made from nothing or by no recipe at all
+    RawInputs,
+    /// Building-ticks spent across the whole chain
+    Footprint,
+}
+
+impl Factory {
+    /// Extra raw input needed for `stream_name` to make one more `product` per minute.
+    /// Raw inputs are the outputs of streams with no inputs of their own, and a product with several sources is drawn from them evenly
+    pub fn marginal_inputs(&self, stream_name: &str, product: &Product) -> HashMap<Product, Rate> {
+        let mut raw: HashMap<Product, f64> = HashMap::new();
+
+        if let Some(stream) = self.streams.get(stream_name) {
+            let per_minute = 1.0 / (self.profile.ticks_per_second * 60.0);
+            Self::raw_inputs_for(stream, product, per_minute, &mut raw, &mut Vec::new());
+        }
+
+        raw.into_iter().map(|(product, per_tick)| (product, Rate::from_per_tick(per_tick))).collect()
+    }
+
+    /// Adds the raw inputs `stream` needs to make `amount` of `product` into `raw`
+    fn raw_inputs_for(stream: &Rc<RefCell<Stream>>, product: &Product, amount: f64, raw: &mut HashMap<Product, f64>, path: &mut Vec<Rc<RefCell<Stream>>>) {
+        if path.iter().any(|seen| Rc::ptr_eq(seen, stream)) {
+            return;
+        }
+
+        let borrowed = stream.borrow();
+        let recipe = borrowed.recipe.borrow();
+
+        if borrowed.inputs().inner.is_empty() {
+            *raw.entry(*product).or_insert(0.0) += amount;
+            return;
+        }
+
+        let produced: usize = recipe.outputs.iter().filter(|output| &*output.product.borrow() == product).map(|output| output.amount).sum();
+
+        if produced == 0 {
+            return;
+        }
+
+        path.push(stream.clone());
+
+        let mut seen: Vec<Product> = Vec::new();
+
+        for input in &recipe.inputs {
+            let input = *input.product.borrow();
+
+            if seen.contains(&input) {
+                continue;
+            }
+
+            seen.push(input);
+
+            let needed = recipe.required_of(&input).unwrap() as f64 / produced as f64 * amount;
+            let sources: Vec<_> = borrowed.inputs().inner.iter().filter(|(source_product, _, _)| *source_product.borrow() == input).collect();
+
+            for (_, source, _) in &sources {
+                Self::raw_inputs_for(source, &input, needed / sources.len() as f64, raw, path);
+            }
+        }
+
+        path.pop();
+    }
+
+    /// How much each final output's rate would change if `recipe_name` took `delta_ticks` more ticks per cycle, keyed by
+    /// `stream -> product` like the summary. The ordering says which way it moved, `Greater` for a gain and `Less` for a drop.
+    /// Works on a copy, so `self` is left untouched, and is empty if there's no such recipe
+    pub fn sensitivity(&self, recipe_name: &str, delta_ticks: f64) -> Vec<(String, Rate, Ordering)> {
+        let Some(recipe) = self.find_recipe("factory", recipe_name) else {
+            return Vec::new();
+        };
+
+        let period = recipe.borrow().rate.period();
+        let mut factory = self.deep_clone();
+        factory.set_recipe_period(recipe_name, (period + delta_ticks).max(1.0)).expect("the copy has every recipe the original does");
+
+        self.final_outputs().into_iter().map(|(name, product)| {
+            let before = self.streams[&name].borrow().rate_of(&product).unwrap_or(Rate::ZERO);
+            let after = factory.streams[&name].borrow().rate_of(&product).unwrap_or(Rate::ZERO);
+            let direction = after.cmp(&before);
+            let change = if direction == Ordering::Less { before - after } else { after - before };
+
+            (format!("{name} -> {}", self.product_label(product)), change, direction)
+        }).collect()
+    }
+
+    /// For each output of `stream_name`, items per tick it could make at full efficiency minus what all of its consumers want.
+    /// Negative values mean the stream is overcommitted
+    pub fn spare_capacity(&self, stream_name: &str) -> HashMap<Product, f64> {
+        let Some(stream) = self.streams.get(stream_name) else {
+            return HashMap::new();
+        };
+
+        let outputs: Vec<Product> = stream.borrow().recipe.borrow().outputs.iter().map(|output| *output.product.borrow()).collect();
+
+        outputs.into_iter().filter_map(|product| {
+            let capacity = stream.borrow().recipe.borrow().optimal_outflow_of(&product)? * stream.borrow().mult();
+            Some((product, capacity.normalize() - self.demand_on(stream, &product)))
+        }).collect()
+    }
+
+    /// Projected efficiency of each consumer of `producer_stream` if a new consumer took `new_demand` of its main (first) output.
+    /// The producer's output is shared in proportion to demand, so every consumer of that product is starved by the same ratio.
+    /// Consumers are keyed by [`Factory::stream_name`], and `self` is left untouched
+    pub fn preview_consumer(&self, producer_stream: &str, new_demand: Rate) -> HashMap<String, Efficiency> {
+        let Some(producer) = self.streams.get(producer_stream) else {
+            return HashMap::new();
+        };
+
+        let Some(product) = producer.borrow().recipe.borrow().outputs.iter().find(|output| !output.voided).map(|output| *output.product.borrow()) else {
+            return HashMap::new();
+        };
+
+        let capacity = producer.borrow().rate_of(&product).map(|rate| rate.normalize()).unwrap_or(0.0);
+        let demand = self.demand_on(producer, &product) + new_demand.normalize();
+        let supplied = if demand > 0.0 { (capacity / demand).min(1.0) } else { 1.0 };
+
+        self.consumers_of(producer, &product).iter().map(|consumer| {
+            let efficiency = self.efficiency_of(&consumer.borrow()).min(supplied);
+
+            (self.stream_name(consumer), efficiency)
+        }).collect()
+    }
+
+    /// Name of the registered recipe making `product` most cheaply by `metric`, counting the cheapest recipe for each input down the chain.
+    /// Ties go to the alphabetically first recipe, and `None` means no recipe produces the product
+    pub fn suggest_recipe(&self, product: &Product, metric: OptMetric) -> Option<String> {
+        let mut names = self.recipes_producing(product);
+        names.retain(|name| {
+            self.recipes[name].borrow().outputs.iter().any(|output| !output.voided && &*output.product.borrow() == product)
+        });
+
+        names.into_iter()
+            .map(|name| {
+                let cost = self.recipe_cost(&self.recipes[&name].borrow(), product, metric, &mut vec![*product]);
+                (name, cost)
+            })
+            .min_by(|lhs, rhs| lhs.1.total_cmp(&rhs.1))
+            .map(|(name, _)| name)
+    }
+
+    /// Cost by `metric` of one `product` from the cheapest recipe for it, or of the product itself if it's raw
+    fn product_cost(&self, product: &Product, metric: OptMetric, path: &mut Vec<Product>) -> f64 {
+        let mut names = self.recipes_producing(product);
+        names.retain(|name| self.recipes[name].borrow().outputs.iter().any(|output| !output.voided && &*output.product.borrow() == product));
+
+        if names.is_empty() {
+            return match metric {
+                OptMetric::RawInputs => 1.0,
+                OptMetric::Footprint => 0.0,
+            };
+        }
+
+        path.push(*product);
+
+        let cost = names.iter()
+            .map(|name| self.recipe_cost(&self.recipes[name].borrow(), product, metric, path))
+            .fold(f64::INFINITY, f64::min);
+
+        path.pop();
+        cost
+    }
+
+    /// Cost by `metric` of one `product` made by `recipe`. Products already on `path` would loop, so they cost infinitely much
+    fn recipe_cost(&self, recipe: &Recipe, product: &Product, metric: OptMetric, path: &mut Vec<Product>) -> f64 {
+        let Some(outflow) = recipe.optimal_outflow_of(product) else {
+            return f64::INFINITY;
+        };
+
+        let produced = outflow.amount as f64;
+
+        let own = match metric {
+            OptMetric::RawInputs if recipe.inputs.is_empty() => 1.0,
+            OptMetric::RawInputs => 0.0,
+            OptMetric::Footprint => recipe.buildings as f64 * recipe.rate.period() / produced,
+        };
+
+        let mut seen: Vec<Product> = Vec::new();
+        let mut inputs = 0.0;
+
+        for input in &recipe.inputs {
+            let input = *input.product.borrow();
+
+            if seen.contains(&input) {
+                continue;
+            }
+
+            seen.push(input);
+
+            if path.contains(&input) {
+                return f64::INFINITY;
+            }
+
+            inputs += recipe.required_of(&input).unwrap() as f64 / produced * self.product_cost(&input, metric, path);
+        }
+
+        own + inputs
+    }
+
+    /// Estimated ticks between a unit entering `from_stream` and it contributing to `to_stream`'s output.
+    /// Each stream along the path adds its period, and each hop adds the time to fill one cycle's worth of input at the supplier's rate.
+    /// Returns `None` if either stream doesn't exist or `to_stream` doesn't draw from `from_stream`
+    pub fn chain_latency(&self, from_stream: &str, to_stream: &str) -> Option<usize> {
+        let from = self.streams.get(from_stream)?;
+        let to = self.streams.get(to_stream)?;
+
+        // breadth first search upstream from `to`, remembering which consumer each stream was reached from
+        let mut queue: Vec<Hop> = vec![(to.clone(), None, None)];
+        let mut idx = 0;
+
+        while idx < queue.len() {
+            let stream = queue[idx].0.clone();
+
+            if Rc::ptr_eq(&stream, from) {
+                break;
+            }
+
+            for (product, input, _) in &stream.borrow().inputs().inner {
+                if !queue.iter().any(|(seen, _, _)| Rc::ptr_eq(seen, input)) {
+                    queue.push((input.clone(), Some(idx), Some(*product.borrow())));
+                }
+            }
+
+            idx += 1;
+        }
+
+        if idx == queue.len() {
+            return None;
+        }
+
+        let mut latency = from.borrow().ticks as f64;
+
+        while let (Some(consumer), Some(product)) = (queue[idx].1, queue[idx].2) {
+            let supplier = queue[idx].0.borrow();
+            let stream = queue[consumer].0.borrow();
+            let needed = stream.recipe.borrow().required_of(&product).unwrap_or(0) * stream.machines();
+            let fill = match supplier.rate_of(&product) {
+                Some(rate) if rate.normalize() > 0.0 => needed as f64 / rate.normalize(),
+                _ => return None,
+            };
+
+            latency += fill + stream.ticks as f64;
+            idx = consumer;
+        }
+
+        Some(latency.ceil() as usize)
+    }
+
+    /// Fuses `stream_name` and the streams upstream of it into one equivalent recipe.
+    /// An input stream is only pulled into the chain if the chain is its only consumer and it's the chain's only source of that product,
+    /// anything else stays a raw input of the fused recipe. The result runs at the root stream's period and current effective rate
+    pub fn fuse_chain(&self, stream_name: &str) -> Option<Recipe> {
+        let root = self.streams.get(stream_name)?.clone();
+        let all_streams = self.all_streams();
+        let mut members = vec![root.clone()];
+        let mut internal: Vec<(Product, Rc<RefCell<Stream>>)> = Vec::new();
+        let mut idx = 0;
+
+        while idx < members.len() {
+            let stream = members[idx].clone();
+            idx += 1;
+
+            for (product, input, _) in &stream.borrow().inputs().inner {
+                let sole_source = stream.borrow().inputs().inner.iter().filter(|(other, _, _)| other == product).count() == 1;
+                let sole_consumer = all_streams.iter().all(|consumer| {
+                    Rc::ptr_eq(consumer, &stream) || !consumer.borrow().inputs().inner.iter().any(|(_, source, _)| Rc::ptr_eq(source, input))
+                });
+
+                if sole_source && sole_consumer && !members.iter().any(|member| Rc::ptr_eq(member, input)) {
+                    internal.push((*product.borrow(), input.clone()));
+                    members.push(input.clone());
+                }
+            }
+        }
+
+        let is_internal = |product: &Product, source: &Rc<RefCell<Stream>>| {
+            internal.iter().any(|(other, stream)| other == product && Rc::ptr_eq(stream, source))
+        };
+
+        let period = root.borrow().recipe.borrow().rate.period();
+        let per_cycle = |rate: Rate| (rate.normalize() * period).round().max(1.0) as usize;
+        let mut inputs: Vec<RecipePart> = Vec::new();
+        let mut outputs: Vec<RecipePart> = Vec::new();
+
+        for member in &members {
+            let stream = member.borrow();
+            let efficiency = stream.efficiency();
+
+            let mut seen: Vec<Product> = Vec::new();
+
+            for (product, source, _) in &stream.inputs().inner {
+                if is_internal(&product.borrow(), source) || seen.contains(&product.borrow()) {
+                    continue;
+                }
+
+                seen.push(*product.borrow());
+
+                if let Some(inflow) = stream.optimal_inflow_of(&product.borrow()) {
+                    push_part(&mut inputs, product, per_cycle(inflow * efficiency));
+                }
+            }
+
+            let products: Vec<Rc<RefCell<Product>>> = stream.recipe.borrow().outputs.iter().filter(|output| !output.voided).fold(Vec::new(), |mut acc, output| {
+                if !acc.contains(&output.product) {
+                    acc.push(output.product.clone());
+                }
+
+                acc
+            });
+
+            for product in products {
+                if is_internal(&product.borrow(), member) {
+                    continue;
+                }
+
+                if let Some(rate) = stream.rate_of(&product.borrow()) {
+                    push_part(&mut outputs, &product, per_cycle(rate));
+                }
+            }
+        }
+
+        let module = root.borrow().recipe.borrow().module;
+        let knowledge = root.borrow().recipe.borrow().knowledge.iter()
+            .map(|(knowledge, amount)| (knowledge.clone(), amount * root.borrow().machines()))
+            .collect();
+
+        Some(Recipe {
+            rate: Rate::from_f64_ticks(1, period),
+            inputs,
+            outputs,
+            knowledge,
+            unlocked: members.iter().all(|member| member.borrow().recipe.borrow().unlocked),
+            buildings: members.iter().map(|member| member.borrow().buildings()).sum(),
+            tags: HashSet::new(),
+            machine: None,
+            power: members.iter()
+                .filter_map(|member| Some(member.borrow().recipe.borrow().power? * member.borrow().mult().to_f64()))
+                .reduce(|lhs, rhs| lhs + rhs),
+            module,
+        })
+    }
+}
+
+/// Adds `amount` of `product` to `parts`, merging with an existing part for the same product
+fn push_part(parts: &mut Vec<RecipePart>, product: &Rc<RefCell<Product>>, amount: usize) {
+    if let Some(part) = parts.iter_mut().find(|part| part.product == *product) {
+        part.amount += amount;
+    } else {
+        parts.push(RecipePart { product: product.clone(), amount, voided: false });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::{factory::iron_gear_chain, rate::Rate};
+
+    #[test]
+    fn slower_smelter_drops_final_output() {
+        let factory = iron_gear_chain();
+
+        // iron at half speed starves gear down to half of its 1 per 2000 ticks
+        assert_eq!(factory.sensitivity("Iron", 1000.0), vec![("gear -> p_gear".to_owned(), Rate::new(1, 4000), Ordering::Less)]);
+        // a faster gear recipe is still held back by iron, so nothing moves
+        assert_eq!(factory.sensitivity("Gear", -1000.0), vec![("gear -> p_gear".to_owned(), Rate::ZERO, Ordering::Equal)]);
+        assert_eq!(factory.streams["gear"].borrow().efficiency(), 1.0);
+        assert!(factory.sensitivity("Nothing", 1000.0).is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{lang::parser::{Expr, InfixOp, Literal}, rate::Rate, Efficiency, Stream};
+
+use super::{Factory, FactoryError};
+
+/// Wires up a [`Factory`] without writing a blueprint, for tests.
+/// Products and recipes go in the `base` module like a mod's would, and streams are named like the factory blueprint's.
+/// The first error stops the rest of the chain and is returned from [`FactoryBuilder::build`]
+///
+/// ```ignore
+/// let factory = FactoryBuilder::new()
+///     .product("p_iron")
+///     .product("p_gear")
+///     .recipe("Iron", &[], &[("p_iron", 2)], 1000)
+///     .recipe("Gear", &[("p_iron", 4)], &[("p_gear", 1)], 2000)
+///     .stream("iron", "Iron", &[], 4)
+///     .stream("gear", "Gear", &["iron"], 1)
+///     .build()?;
+/// ```
+pub struct FactoryBuilder {
+    factory: Factory,
+    error: Option<FactoryError>,
+}
+
+impl Default for FactoryBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FactoryBuilder {
+    pub fn new() -> Self {
+        Self::from_factory(Factory::new())
+    }
+
+    /// Starts from an existing factory, like one made with [`Factory::with_profile`]
+    pub fn from_factory(factory: Factory) -> Self {
+        Self { factory, error: None }
+    }
+
+    pub fn product(self, name: &str) -> Self {
+        self.then(|factory| factory.register_product(name, "base"))
+    }
+
+    /// Registers `name` as `recipe name(inputs) -> outputs / period`, with each part given as a product name and amount
+    pub fn recipe(self, name: &str, inputs: &[(&str, usize)], outputs: &[(&str, usize)], period: usize) -> Self {
+        let inputs = inputs.iter().map(|&(product, amount)| part_expr(product, amount)).collect();
+        let outputs = outputs.iter().map(|&(product, amount)| part_expr(product, amount)).collect();
+        let period = Expr::Literal(Literal::Int(period as isize));
+
+        self.then(|factory| factory.register_recipe(name, inputs, outputs, period, None, "base"))
+    }
+
+    /// Adds a stream running `recipe`, fed by the streams named in `inputs` in the same order as the recipe's inputs
+    pub fn stream(self, name: &str, recipe: &str, inputs: &[&str], mult: usize) -> Self {
+        self.then(|factory| {
+            let parts = factory.find_recipe("factory", recipe).ok_or(FactoryError::InvalidArguments)?.borrow().inputs.clone();
+
+            if parts.len() != inputs.len() {
+                return Err(FactoryError::InvalidArguments);
+            }
+
+            let inputs = parts.iter().zip(inputs).map(|(part, input)| {
+                let stream = factory.streams.get(*input).ok_or(FactoryError::InvalidArguments)?.clone();
+                Ok((*part.product.borrow(), stream))
+            }).collect::<Result<Vec<_>, FactoryError>>()?;
+
+            factory.add_stream(name, recipe, inputs, mult).map(|_| ())
+        })
+    }
+
+    /// Adds `p_iron` and `p_gear`, with `Iron` making 2 iron every 1000 ticks and `Gear` turning `iron_per_gear` iron into a gear
+    /// every `gear_period` ticks, which most tests start from
+    pub fn iron_and_gear(self, iron_per_gear: usize, gear_period: usize) -> Self {
+        self.product("p_iron")
+            .product("p_gear")
+            .recipe("Iron", &[], &[("p_iron", 2)], 1000)
+            .recipe("Gear", &[("p_iron", iron_per_gear)], &[("p_gear", 1)], gear_period)
+    }
+
+    pub fn build(self) -> Result<Factory, FactoryError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.factory),
+        }
+    }
+
+    fn then(mut self, step: impl FnOnce(&mut Factory) -> Result<(), FactoryError>) -> Self {
+        if self.error.is_none() {
+            self.error = step(&mut self.factory).err();
+        }
+
+        self
+    }
+}
+
+/// `product * amount` as it'd be written in a recipe
+fn part_expr(product: &str, amount: usize) -> Expr {
+    let product = Expr::Ident(product.to_owned());
+
+    if amount == 1 {
+        product
+    } else {
+        Expr::InfixOp { lhs: Box::new(product), op: InfixOp::Mul, rhs: Box::new(Expr::Literal(Literal::Int(amount as isize))) }
+    }
+}
+
+/// One `iron` machine feeding one `gear` machine that wants exactly what it makes
+pub fn iron_gear_chain() -> Factory {
+    FactoryBuilder::new()
+        .iron_and_gear(4, 2000)
+        .stream("iron", "Iron", &[], 1)
+        .stream("gear", "Gear", &["iron"], 1)
+        .build()
+        .unwrap()
+}
+
+/// `depth` levels above an ore stream `l0`, each drawing both of its inputs from the level below, so working out the top
+/// level `l<depth>` from scratch visits the ore stream 2^depth times. Mults vary so the levels aren't all at full efficiency
+pub fn binary_tree(depth: usize) -> Factory {
+    let mut builder = FactoryBuilder::new();
+
+    for level in 0..=depth {
+        builder = builder.product(&format!("a{level}")).product(&format!("b{level}"));
+    }
+
+    builder = builder
+        .recipe("L0", &[], &[("a0", 1), ("b0", 1)], 1000)
+        .stream("l0", "L0", &[], 3);
+
+    for level in 1..=depth {
+        let (a, b) = (format!("a{}", level - 1), format!("b{}", level - 1));
+        let below = format!("l{}", level - 1);
+
+        builder = builder
+            .recipe(&format!("L{level}"), &[(&a, 1), (&b, 2)], &[(&format!("a{level}"), 1), (&format!("b{level}"), 2)], 1000)
+            .stream(&format!("l{level}"), &format!("L{level}"), &[&below, &below], 1 + level % 3);
+    }
+
+    builder.build().unwrap()
+}
+
+/// Efficiency worked out from scratch every time, the way it was before caching, to check and time the cache against
+pub fn naive_efficiency(stream: &Stream) -> Efficiency {
+    if stream.inputs().inner.is_empty() {
+        return 1.0;
+    }
+
+    let recipe = stream.recipe.borrow();
+
+    recipe.inputs.iter().map(|input| {
+        let product = *input.product.borrow();
+        let supplied: Rate = stream.inputs().inner.iter().filter_map(|(_, source, cap)| {
+            let source = source.borrow();
+            let rate = source.recipe.borrow().optimal_outflow_of(&product)? * naive_efficiency(&source) * source.mult();
+            Some(cap.map_or(rate, |cap| rate.min(cap)))
+        }).sum();
+
+        (supplied / (recipe.optimal_inflow_of(&product).unwrap() * stream.mult())).unwrap_or(1.0)
+    }).reduce(f64::min).map_or(0.0, |efficiency| efficiency.min(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn wires_streams_in_input_order() {
+        let factory = iron_gear_chain();
+
+        let gear = factory.streams["gear"].borrow();
+        assert_eq!(gear.mult(), 1.into());
+        assert!(Rc::ptr_eq(&gear.inputs().inner[0].1, &factory.streams["iron"]));
+        assert_eq!(gear.efficiency(), 1.0);
+    }
+
+    #[test]
+    fn first_error_is_kept() {
+        let result = FactoryBuilder::new()
+            .product("p_iron")
+            .stream("iron", "Iron", &[], 1)
+            .product("p_iron")
+            .build();
+
+        assert!(matches!(result, Err(FactoryError::InvalidArguments)));
+    }
+}
@@ -0,0 +1,85 @@
+use std::cmp::Ordering;
+
+use crate::rate::Rate;
+
+use super::{FactoryError, Value};
+
+/// Function called with the arguments of a builtin call, already evaluated
+pub type Builtin = fn(Vec<Value>) -> Result<Value, FactoryError>;
+
+/// Builtins by the name they're called with. Anything the blueprint defines with the same name shadows them
+const BUILTINS: &[(&str, Builtin)] = &[
+    ("min", min),
+    ("max", max),
+    ("beltLimit", belt_limit),
+];
+
+pub fn builtin(name: &str) -> Option<Builtin> {
+    BUILTINS.iter().find(|(builtin, _)| *builtin == name).map(|(_, function)| *function)
+}
+
+/// Smallest of some ints, floats or rates
+fn min(args: Vec<Value>) -> Result<Value, FactoryError> {
+    extreme(args, Ordering::Less)
+}
+
+/// Largest of some ints, floats or rates
+fn max(args: Vec<Value>) -> Result<Value, FactoryError> {
+    extreme(args, Ordering::Greater)
+}
+
+/// Caps the link from a stream to the recipe it's passed to, like `Smelt(beltLimit(ore, 15))`.
+/// The cap is a rate, or a plain int of items per tick
+fn belt_limit(args: Vec<Value>) -> Result<Value, FactoryError> {
+    let [source, cap]: [Value; 2] = args.try_into().map_err(|_| FactoryError::InvalidArguments)?;
+
+    let cap = match cap {
+        Value::Rate(cap) => cap,
+        Value::Int(cap) if cap >= 0 => Rate::new(cap as usize, 1),
+        _ => return Err(FactoryError::InvalidArguments),
+    };
+
+    match source {
+        Value::Stream(..) | Value::Call(..) | Value::MultRecipe(..) => Ok(Value::Limited(Box::new(source), cap)),
+        _ => Err(FactoryError::TypeError),
+    }
+}
+
+/// The argument that's `wanted` compared to all the others. Ints and floats can be mixed, giving a float,
+/// but rates only compare with other rates
+fn extreme(args: Vec<Value>, wanted: Ordering) -> Result<Value, FactoryError> {
+    let mut args = args.into_iter();
+    let mut best = args.next().ok_or(FactoryError::InvalidArguments)?;
+
+    for arg in args {
+        let ordering = match (&arg, &best) {
+            (Value::Int(lhs), Value::Int(rhs)) => lhs.partial_cmp(rhs),
+            (Value::Rate(lhs), Value::Rate(rhs)) => Some(lhs.cmp(rhs)),
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                best = Value::Float(as_f64(&best));
+                as_f64(&arg).partial_cmp(&as_f64(&best))
+            },
+            _ => return Err(FactoryError::InvalidArguments),
+        };
+
+        if ordering == Some(wanted) {
+            best = match arg {
+                Value::Int(arg) if matches!(best, Value::Float(_)) => Value::Float(arg as f64),
+                arg => arg,
+            };
+        }
+    }
+
+    match best {
+        Value::Int(_) | Value::Float(_) | Value::Rate(_) => Ok(best),
+        _ => Err(FactoryError::InvalidArguments),
+    }
+}
+
+fn as_f64(value: &Value) -> f64 {
+    match value {
+        Value::Int(value) => *value as f64,
+        Value::Float(value) => *value,
+        _ => unreachable!("only called on numbers"),
+    }
+}
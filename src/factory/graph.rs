@@ -0,0 +1,169 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::Stream;
+
+use super::Factory;
+
+/// What role a stream plays in the factory, used to style exported graphs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    /// Supplied from outside the factory by a `source` statement
+    Boundary,
+    /// Takes no inputs
+    Source,
+    /// Has an output nothing consumes
+    FinalOutput,
+    /// Running below full efficiency
+    Bottleneck,
+    Intermediate,
+}
+
+impl NodeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Boundary => "boundary",
+            Self::Source => "source",
+            Self::FinalOutput => "output",
+            Self::Bottleneck => "bottleneck",
+            Self::Intermediate => "intermediate",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GraphNode {
+    /// Index into the node list, stable for a given factory
+    pub id: usize,
+    pub label: String,
+    pub kind: NodeKind,
+}
+
+/// One wired input, pointing from the supplying stream to the consuming one
+#[derive(Clone, Debug, PartialEq)]
+pub struct GraphEdge {
+    pub from: usize,
+    pub to: usize,
+    pub label: String,
+}
+
+impl Factory {
+    /// Every stream as a node and every wired input as an edge, shared by the graph exporters.
+    /// Named streams come first sorted by name, followed by nested streams in the order they're found
+    pub fn graph(&self) -> (Vec<GraphNode>, Vec<GraphEdge>) {
+        let mut names: Vec<&String> = self.streams.keys().collect();
+        names.sort();
+
+        let mut streams: Vec<Rc<RefCell<Stream>>> = names.into_iter().map(|name| self.streams[name].clone()).collect();
+        let mut idx = 0;
+
+        while idx < streams.len() {
+            let inputs: Vec<_> = streams[idx].borrow().inputs().inner.iter().map(|(_, input, _)| input.clone()).collect();
+
+            for input in inputs {
+                if !streams.iter().any(|seen| Rc::ptr_eq(seen, &input)) {
+                    streams.push(input);
+                }
+            }
+
+            idx += 1;
+        }
+
+        let index_of = |stream: &Rc<RefCell<Stream>>| streams.iter().position(|seen| Rc::ptr_eq(seen, stream)).unwrap();
+
+        let nodes = streams.iter().enumerate().map(|(id, stream)| {
+            let name = match self.streams.iter().find(|(_, named)| Rc::ptr_eq(named, stream)) {
+                Some((name, _)) => name.to_owned(),
+                None => format!("({})", self.recipe_name(&stream.borrow().recipe).unwrap_or_else(|| "<unnamed>".to_owned())),
+            };
+
+            let borrowed = stream.borrow();
+            let kind = if borrowed.source {
+                NodeKind::Boundary
+            } else if self.efficiency_of(&borrowed) < 1.0 {
+                NodeKind::Bottleneck
+            } else if borrowed.inputs().inner.is_empty() {
+                NodeKind::Source
+            } else if borrowed.recipe.borrow().outputs.iter().any(|output| !output.voided && self.consumers_of(stream, &output.product.borrow()).is_empty()) {
+                NodeKind::FinalOutput
+            } else {
+                NodeKind::Intermediate
+            };
+
+            GraphNode { id, label: format!("{name} x{}", borrowed.mult()), kind }
+        }).collect();
+
+        let mut edges = Vec::new();
+
+        for (to, stream) in streams.iter().enumerate() {
+            for (product, input, _) in &stream.borrow().inputs().inner {
+                let product = *product.borrow();
+                let product_name = self.product_label(product);
+                let label = match input.borrow().rate_of(&product) {
+                    Some(rate) => format!("{product_name} @ {rate}"),
+                    None => product_name,
+                };
+
+                edges.push(GraphEdge { from: index_of(input), to, label });
+            }
+        }
+
+        (nodes, edges)
+    }
+
+    /// The factory as a Mermaid `flowchart TD`, with classes marking supplies from outside the factory, raw sources,
+    /// final outputs and bottlenecks
+    pub fn to_mermaid(&self) -> String {
+        let (nodes, edges) = self.graph();
+        let mut out = "flowchart TD\n".to_owned();
+
+        for node in &nodes {
+            out += &format!("    s{}[\"{}\"]", node.id, node.label.replace('"', "#quot;"));
+
+            if node.kind != NodeKind::Intermediate {
+                out += &format!(":::{}", node.kind.name());
+            }
+
+            out += "\n";
+        }
+
+        for edge in &edges {
+            out += &format!("    s{} -->|\"{}\"| s{}\n", edge.from, edge.label.replace('"', "#quot;"), edge.to);
+        }
+
+        out += "    classDef boundary fill:#eee,stroke:#666,stroke-dasharray:4\n";
+        out += "    classDef source fill:#dfd,stroke:#393\n";
+        out += "    classDef output fill:#ddf,stroke:#339\n";
+        out += "    classDef bottleneck fill:#fdd,stroke:#c33\n";
+        out
+    }
+
+    /// The factory as a Graphviz digraph, for piping through `dot -Tsvg`. Edges into streams below full efficiency are red,
+    /// and supplies from outside the factory are dashed
+    pub fn to_dot(&self) -> String {
+        let (nodes, edges) = self.graph();
+        let mut out = "digraph factory {\n".to_owned();
+
+        for node in &nodes {
+            out += &format!("    s{} [label=\"{}\"", node.id, node.label.replace('"', "\\\""));
+
+            if node.kind == NodeKind::Boundary {
+                out += ", shape=invhouse, style=dashed";
+            }
+
+            out += "];\n";
+        }
+
+        for edge in &edges {
+            out += &format!("    s{} -> s{} [label=\"{}\"", edge.from, edge.to, edge.label.replace('"', "\\\""));
+
+            if nodes[edge.to].kind == NodeKind::Bottleneck {
+                out += ", color=red, fontcolor=red";
+            }
+
+            out += "];\n";
+        }
+
+        out += "}\n";
+        out
+    }
+}
@@ -0,0 +1,62 @@
+use std::{fs::read_to_string, path::{Path, PathBuf}};
+
+use crate::lang::{self, Expr};
+
+use super::{Factory, FactoryError};
+
+/// Files seen while splicing in includes
+#[derive(Clone, Debug, Default)]
+pub(super) struct Includes {
+    /// Files currently being expanded, outermost first, so one including itself can be reported
+    stack: Vec<PathBuf>,
+    /// Files already spliced in, which are skipped if included again
+    done: Vec<PathBuf>,
+}
+
+impl Factory {
+    /// Reads, lexes and parses the blueprint at `path`, splicing in the files it includes
+    pub fn load_blueprint(path: impl AsRef<Path>) -> Result<Vec<Expr>, FactoryError> {
+        Self::load_included(path.as_ref(), &mut Includes::default())
+    }
+
+    fn load_included(path: &Path, includes: &mut Includes) -> Result<Vec<Expr>, FactoryError> {
+        let error = |reason: String| FactoryError::Include(path.display().to_string(), reason);
+        let canonical = path.canonicalize().map_err(|err| error(err.to_string()))?;
+
+        if let Some(start) = includes.stack.iter().position(|seen| *seen == canonical) {
+            let mut cycle: Vec<String> = includes.stack[start..].iter().map(|seen| seen.display().to_string()).collect();
+            cycle.push(canonical.display().to_string());
+            return Err(FactoryError::Cycle(cycle));
+        }
+
+        // diamonds would otherwise define everything in the shared file twice
+        if includes.done.contains(&canonical) {
+            return Ok(Vec::new());
+        }
+
+        let src = read_to_string(&canonical).map_err(|err| error(err.to_string()))?;
+        let tokens = lang::tokenize(&src).map_err(|err| error(err.to_string()))?;
+        let ast = lang::parse_tokens(tokens).map_err(|err| error(err.to_string()))?;
+
+        includes.stack.push(canonical.clone());
+        let ast = Self::expand_includes(ast, canonical.parent().unwrap_or(Path::new(".")), includes)?;
+        includes.stack.pop();
+        includes.done.push(canonical);
+
+        Ok(ast)
+    }
+
+    /// Replaces each `include` in `ast` with the statements of the file it names, resolved relative to `dir`
+    pub(super) fn expand_includes(ast: Vec<Expr>, dir: &Path, includes: &mut Includes) -> Result<Vec<Expr>, FactoryError> {
+        let mut out = Vec::with_capacity(ast.len());
+
+        for expr in ast {
+            match expr {
+                Expr::Include(path) => out.extend(Self::load_included(&dir.join(path), includes)?),
+                expr => out.push(expr),
+            }
+        }
+
+        Ok(out)
+    }
+}
@@ -0,0 +1,100 @@
+use std::str::FromStr;
+
+use super::{DEFAULT_BUF_MULT, DEFAULT_TICKS_PER_SECOND};
+
+/// How fractional machine counts are turned into whole ones
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RoundingMode {
+    /// Always enough machines, possibly with some idle time
+    #[default]
+    Up,
+    /// Whichever whole number is closest, for planning by hand where a little idle time or shortfall is fine
+    Nearest,
+    /// Never any idle machines, possibly falling short of demand
+    Down,
+}
+
+impl RoundingMode {
+    pub fn apply(&self, machines: f64) -> f64 {
+        match self {
+            // demand that divides exactly shouldn't round up from float error
+            Self::Up => (machines - f64::EPSILON).ceil(),
+            Self::Nearest => machines.round(),
+            Self::Down => machines.floor(),
+        }
+    }
+}
+
+impl FromStr for RoundingMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "up" => Ok(Self::Up),
+            "nearest" => Ok(Self::Nearest),
+            "down" => Ok(Self::Down),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Timing and sizing conventions of a particular game
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GameProfile {
+    pub name: &'static str,
+    pub ticks_per_second: f64,
+    pub rounding: RoundingMode,
+    /// Whether a stream can run a fraction of a machine, like underclocking in Satisfactory
+    pub fractional_machines: bool,
+    /// Default buffers hold this many cycles of a stream's inputs and outputs
+    pub buffer_mult: usize,
+}
+
+impl GameProfile {
+    /// Millisecond ticks, matching periods written in blueprints
+    pub const DEFAULT: Self = Self {
+        name: "default",
+        ticks_per_second: DEFAULT_TICKS_PER_SECOND,
+        rounding: RoundingMode::Up,
+        fractional_machines: false,
+        buffer_mult: DEFAULT_BUF_MULT,
+    };
+
+    pub const FACTORIO: Self = Self {
+        name: "factorio",
+        ticks_per_second: 60.0,
+        rounding: RoundingMode::Up,
+        fractional_machines: false,
+        buffer_mult: 2,
+    };
+
+    pub const SATISFACTORY: Self = Self {
+        name: "satisfactory",
+        ticks_per_second: DEFAULT_TICKS_PER_SECOND,
+        rounding: RoundingMode::Up,
+        fractional_machines: true,
+        buffer_mult: 2,
+    };
+
+    pub const DYSON_SPHERE_PROGRAM: Self = Self {
+        name: "dsp",
+        ticks_per_second: 60.0,
+        rounding: RoundingMode::Up,
+        fractional_machines: false,
+        buffer_mult: 4,
+    };
+
+    pub const PRESETS: [Self; 4] = [Self::DEFAULT, Self::FACTORIO, Self::SATISFACTORY, Self::DYSON_SPHERE_PROGRAM];
+
+    /// Looks up a preset by its name
+    pub fn preset(name: &str) -> Option<Self> {
+        Self::PRESETS.into_iter().find(|profile| profile.name == name)
+    }
+}
+
+impl Default for GameProfile {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
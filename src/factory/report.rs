@@ -0,0 +1,75 @@
+use std::fmt::Display;
+
+use crate::lang::Span;
+
+use super::{Factory, FactoryError};
+
+impl FactoryError {
+    /// Where in the source the error came from, for errors that came from somewhere in particular
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::Syntax(_, span) | Self::At(span, _) => Some(*span),
+            _ => None,
+        }
+    }
+}
+
+impl Display for FactoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedEof => write!(f, "unexpected end of input"),
+            Self::TypeError => write!(f, "mismatched types"),
+            Self::Exists(name) => write!(f, "`{name}` is already defined"),
+            Self::InvalidArguments => write!(f, "invalid arguments"),
+            Self::UnknownWarning(kind) => write!(f, "there's no warning called `{kind}`"),
+            Self::ModuleLocked(module) => write!(f, "module `{module}` is locked"),
+            Self::Unbuffered(product) => write!(f, "the stream has no buffer for `{product}`"),
+            Self::DivideByZero => write!(f, "division by zero"),
+            Self::Undefined(name) => write!(f, "`{name}` isn't defined"),
+            Self::Cycle(streams) => write!(f, "streams feed into themselves: {}", streams.join(" -> ")),
+            Self::FractionalMult => write!(f, "that would leave a stream running part of a machine"),
+            Self::Include(path, reason) => write!(f, "couldn't include `{path}`: {reason}"),
+            Self::Syntax(message, _) => write!(f, "{message}"),
+            Self::At(_, inner) => write!(f, "{inner}"),
+            Self::AssertionFailed(condition) => write!(f, "assertion failed: {condition}"),
+            Self::AmbiguousRecipe(name, candidates) => {
+                write!(f, "more than one variant of `{name}` takes these arguments: {}", candidates.join("; "))
+            },
+            Self::OverBudget(machine, budget) => write!(f, "`{machine}` runs more streams than its budget of {budget} machines"),
+            Self::OverSupply(product) => write!(f, "the supply of `{product}` is too small to run every source that extracts it"),
+        }
+    }
+}
+
+impl Factory {
+    /// Renders `err`'s message, followed by the line of `src` it came from with the offending part underlined.
+    /// `src` has to be the source the error's span points into, like the line given to [`Factory::eval_line`]
+    pub fn report_error(src: &str, err: &FactoryError) -> String {
+        let message = err.to_string();
+
+        let Some(span) = err.span() else {
+            return format!("error: {message}");
+        };
+
+        // spans count chars rather than bytes
+        let byte = |chars: usize| src.char_indices().nth(chars).map_or(src.len(), |(byte, _)| byte);
+        let start = byte(span.start);
+        let end = byte(span.end).max(start);
+
+        let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = src[start..].find('\n').map_or(src.len(), |i| start + i);
+        let line = src[..line_start].matches('\n').count() + 1;
+        let column = src[line_start..start].chars().count();
+        // running into the end of the input still gets one caret, just past the last character
+        let width = src[start..end.min(line_end)].chars().count().max(1);
+        let gutter = " ".repeat(line.to_string().len());
+
+        format!(
+            "error: {message}\n{gutter}--> {line}:{}\n{gutter} |\n{line} | {}\n{gutter} | {}{}",
+            column + 1,
+            &src[line_start..line_end],
+            " ".repeat(column),
+            "^".repeat(width),
+        )
+    }
+}
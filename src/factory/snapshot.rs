@@ -0,0 +1,395 @@
+use std::{cell::RefCell, collections::{HashMap, HashSet}, rc::Rc};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{mult::Mult, rate::Rate, Buffer, EfficiencyCache, EfficiencyGeneration, EfficiencyModel, InputStreams, Product, Recipe, RecipePart, Stream};
+
+use super::{Factory, FactoryError, GameProfile, Knowledge, RoundingMode, WarningKind};
+
+/// Index into one of a [`FactorySnapshot`]'s arenas
+type Id = usize;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct PartSnapshot {
+    product: Id,
+    amount: usize,
+    voided: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct RecipeSnapshot {
+    rate: Rate,
+    inputs: Vec<PartSnapshot>,
+    outputs: Vec<PartSnapshot>,
+    knowledge: Vec<(Id, usize)>,
+    unlocked: bool,
+    buildings: usize,
+    tags: HashSet<String>,
+    machine: Option<String>,
+    power: Option<f64>,
+    module: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct KnowledgeSnapshot {
+    name: String,
+    progress: Buffer,
+    recipes: Vec<Id>,
+    dependencies: Vec<Id>,
+    unlocked: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StreamSnapshot {
+    mult: Mult,
+    recipe: Id,
+    /// The product, the stream it's taken from, and the link's cap if it has one
+    inputs: Vec<(Id, Id, Option<Rate>)>,
+    buffers: Vec<(Product, Buffer)>,
+    next: Option<usize>,
+    ticks: usize,
+    carry: usize,
+    allow: HashSet<WarningKind>,
+    tags: HashSet<String>,
+    source: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ProfileSnapshot {
+    name: String,
+    ticks_per_second: f64,
+    rounding: RoundingMode,
+    fractional_machines: bool,
+    buffer_mult: usize,
+}
+
+/// A factory with every shared product, recipe, knowledge and stream stored once in an arena and referred to by index,
+/// so loading it hands out the same `Rc` everywhere the original did
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FactorySnapshot {
+    products: Vec<Product>,
+    recipes: Vec<RecipeSnapshot>,
+    knowledge: Vec<KnowledgeSnapshot>,
+    /// Named streams first, then streams only reachable as inputs
+    streams: Vec<StreamSnapshot>,
+    product_ids: Vec<(String, Id)>,
+    product_names: Vec<(Product, String)>,
+    recipe_ids: Vec<(String, Id)>,
+    recipe_variants: Vec<(String, Vec<Id>)>,
+    knowledge_ids: Vec<(String, Id)>,
+    stream_ids: Vec<(String, Id)>,
+    unresolved: Vec<String>,
+    modules: HashMap<String, usize>,
+    verbose: bool,
+    profile: ProfileSnapshot,
+    efficiency_model: EfficiencyModel,
+    machine_budgets: HashMap<String, usize>,
+    raw_supply: Vec<(Product, Rate)>,
+    target_efficiency: f64,
+    stream_targets: HashMap<String, f64>,
+    locked_modules: HashSet<String>,
+    check_conservation: bool,
+    tick: usize,
+}
+
+/// Shared values in the order they were first seen
+struct Arena<T>(Vec<Rc<RefCell<T>>>);
+
+impl<T> Arena<T> {
+    fn id(&mut self, item: &Rc<RefCell<T>>) -> Id {
+        match self.0.iter().position(|seen| Rc::ptr_eq(seen, item)) {
+            Some(id) => id,
+            None => {
+                self.0.push(item.clone());
+                self.0.len() - 1
+            }
+        }
+    }
+}
+
+fn sorted<T>(map: &HashMap<String, T>) -> Vec<(&String, &T)> {
+    let mut entries: Vec<(&String, &T)> = map.iter().collect();
+    entries.sort_by(|lhs, rhs| lhs.0.cmp(rhs.0));
+    entries
+}
+
+fn lookup<T: Clone>(arena: &[T], id: Id) -> Result<T, FactoryError> {
+    arena.get(id).cloned().ok_or(FactoryError::InvalidArguments)
+}
+
+fn named<T: Clone>(ids: &[(String, Id)], arena: &[T]) -> Result<HashMap<String, T>, FactoryError> {
+    ids.iter().map(|(name, id)| Ok((name.to_owned(), lookup(arena, *id)?))).collect()
+}
+
+impl Factory {
+    pub fn snapshot(&self) -> FactorySnapshot {
+        let mut products = Arena(Vec::new());
+        let mut recipes = Arena(Vec::new());
+        let mut knowledge = Arena(Vec::new());
+        let mut streams = Arena(Vec::new());
+
+        let product_ids = sorted(&self.products).into_iter().map(|(name, product)| (name.to_owned(), products.id(product))).collect();
+        let recipe_ids = sorted(&self.recipes).into_iter().map(|(name, recipe)| (name.to_owned(), recipes.id(recipe))).collect();
+        let recipe_variants = sorted(&self.recipe_variants).into_iter()
+            .map(|(name, variants)| (name.to_owned(), variants.iter().map(|recipe| recipes.id(recipe)).collect()))
+            .collect();
+        let knowledge_ids = sorted(&self.knowledge).into_iter().map(|(name, knowledge_ref)| (name.to_owned(), knowledge.id(knowledge_ref))).collect();
+        let stream_ids = sorted(&self.streams).into_iter().map(|(name, stream)| (name.to_owned(), streams.id(stream))).collect();
+
+        let mut stream_snapshots = Vec::new();
+        let mut recipe_snapshots = Vec::new();
+        let mut knowledge_snapshots = Vec::new();
+
+        // snapshotting one value can discover more, so keep going until every arena has been walked
+        loop {
+            if let Some(stream) = streams.0.get(stream_snapshots.len()).cloned() {
+                let stream = stream.borrow();
+                let mut buffers: Vec<(Product, Buffer)> = stream.buffers.iter().map(|(product, buffer)| (*product, *buffer)).collect();
+                buffers.sort_by_key(|(product, _)| (product.module, product.id));
+
+                stream_snapshots.push(StreamSnapshot {
+                    mult: stream.mult(),
+                    recipe: recipes.id(&stream.recipe),
+                    inputs: stream.inputs().inner.iter().map(|(product, input, cap)| (products.id(product), streams.id(input), *cap)).collect(),
+                    buffers,
+                    next: stream.next,
+                    ticks: stream.ticks,
+                    carry: stream.carry,
+                    allow: stream.allow.clone(),
+                    tags: stream.tags.clone(),
+                    source: stream.source,
+                });
+            } else if let Some(recipe) = recipes.0.get(recipe_snapshots.len()).cloned() {
+                let recipe = recipe.borrow();
+                let mut parts = |parts: &[RecipePart]| parts.iter().map(|part| PartSnapshot {
+                    product: products.id(&part.product),
+                    amount: part.amount,
+                    voided: part.voided,
+                }).collect::<Vec<_>>();
+
+                let inputs = parts(&recipe.inputs);
+                let outputs = parts(&recipe.outputs);
+
+                recipe_snapshots.push(RecipeSnapshot {
+                    rate: recipe.rate,
+                    inputs,
+                    outputs,
+                    knowledge: recipe.knowledge.iter().map(|(knowledge_ref, amount)| (knowledge.id(knowledge_ref), *amount)).collect(),
+                    unlocked: recipe.unlocked,
+                    buildings: recipe.buildings,
+                    tags: recipe.tags.clone(),
+                    machine: recipe.machine.clone(),
+                    power: recipe.power,
+                    module: recipe.module,
+                });
+            } else if let Some(knowledge_ref) = knowledge.0.get(knowledge_snapshots.len()).cloned() {
+                let knowledge_ref = knowledge_ref.borrow();
+
+                knowledge_snapshots.push(KnowledgeSnapshot {
+                    name: knowledge_ref.name.clone(),
+                    progress: knowledge_ref.progress,
+                    recipes: knowledge_ref.recipes.iter().map(|recipe| recipes.id(recipe)).collect(),
+                    dependencies: knowledge_ref.dependencies.iter().map(|dep| knowledge.id(dep)).collect(),
+                    unlocked: knowledge_ref.unlocked,
+                });
+            } else {
+                break;
+            }
+        }
+
+        let mut product_names: Vec<(Product, String)> = self.product_names.iter().map(|(product, name)| (*product, name.to_owned())).collect();
+        product_names.sort_by_key(|(product, _)| (product.module, product.id));
+
+        let mut raw_supply: Vec<(Product, Rate)> = self.raw_supply.iter().map(|(product, rate)| (*product, *rate)).collect();
+        raw_supply.sort_by_key(|(product, _)| (product.module, product.id));
+
+        FactorySnapshot {
+            products: products.0.iter().map(|product| *product.borrow()).collect(),
+            recipes: recipe_snapshots,
+            knowledge: knowledge_snapshots,
+            streams: stream_snapshots,
+            product_ids,
+            product_names,
+            recipe_ids,
+            recipe_variants,
+            knowledge_ids,
+            stream_ids,
+            unresolved: self.unresolved.clone(),
+            modules: self.modules.clone(),
+            verbose: self.verbose,
+            profile: ProfileSnapshot {
+                name: self.profile.name.to_owned(),
+                ticks_per_second: self.profile.ticks_per_second,
+                rounding: self.profile.rounding,
+                fractional_machines: self.profile.fractional_machines,
+                buffer_mult: self.profile.buffer_mult,
+            },
+            efficiency_model: self.efficiency_model,
+            machine_budgets: self.machine_budgets.clone(),
+            raw_supply,
+            target_efficiency: self.target_efficiency,
+            stream_targets: self.stream_targets.clone(),
+            locked_modules: self.locked_modules.clone(),
+            check_conservation: self.check_conservation,
+            tick: self.tick,
+        }
+    }
+
+    /// Rebuilds a factory from [`Factory::snapshot`], erroring if the snapshot refers to an index it doesn't have
+    pub fn from_snapshot(snapshot: FactorySnapshot) -> Result<Self, FactoryError> {
+        let products: Vec<Rc<RefCell<Product>>> = snapshot.products.iter().map(|product| Rc::new(RefCell::new(*product))).collect();
+
+        let parts = |parts: &[PartSnapshot]| parts.iter().map(|part| Ok(RecipePart {
+            product: lookup(&products, part.product)?,
+            amount: part.amount,
+            voided: part.voided,
+        })).collect::<Result<Vec<_>, FactoryError>>();
+
+        // recipes and knowledge point at each other, so both are created empty and linked up afterwards
+        let recipes: Vec<Rc<RefCell<Recipe>>> = snapshot.recipes.iter().map(|recipe| Ok(Rc::new(RefCell::new(Recipe {
+            rate: recipe.rate,
+            inputs: parts(&recipe.inputs)?,
+            outputs: parts(&recipe.outputs)?,
+            knowledge: Vec::new(),
+            unlocked: recipe.unlocked,
+            buildings: recipe.buildings,
+            tags: recipe.tags.clone(),
+            machine: recipe.machine.clone(),
+            power: recipe.power,
+            module: recipe.module,
+        })))).collect::<Result<_, FactoryError>>()?;
+
+        let knowledge: Vec<Rc<RefCell<Knowledge>>> = snapshot.knowledge.iter().map(|knowledge_ref| Rc::new(RefCell::new(Knowledge {
+            name: knowledge_ref.name.clone(),
+            progress: knowledge_ref.progress,
+            recipes: Vec::new(),
+            dependencies: Vec::new(),
+            unlocked: knowledge_ref.unlocked,
+        }))).collect();
+
+        for (recipe, saved) in recipes.iter().zip(&snapshot.recipes) {
+            recipe.borrow_mut().knowledge = saved.knowledge.iter()
+                .map(|(id, amount)| Ok((lookup(&knowledge, *id)?, *amount)))
+                .collect::<Result<_, FactoryError>>()?;
+        }
+
+        for (knowledge_ref, saved) in knowledge.iter().zip(&snapshot.knowledge) {
+            let mut knowledge_ref = knowledge_ref.borrow_mut();
+            knowledge_ref.recipes = saved.recipes.iter().map(|id| lookup(&recipes, *id)).collect::<Result<_, FactoryError>>()?;
+            knowledge_ref.dependencies = saved.dependencies.iter().map(|id| lookup(&knowledge, *id)).collect::<Result<_, FactoryError>>()?;
+        }
+
+        let generation = EfficiencyGeneration::default();
+
+        // same for streams wired into each other
+        let streams: Vec<Rc<RefCell<Stream>>> = snapshot.streams.iter().map(|stream| Ok(Rc::new(RefCell::new(Stream {
+            mult: stream.mult,
+            recipe: lookup(&recipes, stream.recipe)?,
+            inputs: InputStreams::NONE,
+            buffers: stream.buffers.iter().copied().collect(),
+            next: stream.next,
+            ticks: stream.ticks,
+            carry: stream.carry,
+            allow: stream.allow.clone(),
+            tags: stream.tags.clone(),
+            source: stream.source,
+            generation: generation.clone(),
+            efficiency_cache: EfficiencyCache::default(),
+        })))).collect::<Result<_, FactoryError>>()?;
+
+        for (stream, saved) in streams.iter().zip(&snapshot.streams) {
+            let inputs = saved.inputs.iter()
+                .map(|(product, input, cap)| Ok((lookup(&products, *product)?, lookup(&streams, *input)?, *cap)))
+                .collect::<Result<Vec<_>, FactoryError>>()?;
+
+            stream.borrow_mut().set_inputs(inputs.into());
+        }
+
+        let profile = &snapshot.profile;
+
+        Ok(Self {
+            products: named(&snapshot.product_ids, &products)?,
+            product_names: snapshot.product_names.into_iter().collect(),
+            recipes: named(&snapshot.recipe_ids, &recipes)?,
+            recipe_variants: snapshot.recipe_variants.iter()
+                .map(|(name, ids)| Ok((name.to_owned(), ids.iter().map(|id| lookup(&recipes, *id)).collect::<Result<_, FactoryError>>()?)))
+                .collect::<Result<_, FactoryError>>()?,
+            streams: named(&snapshot.stream_ids, &streams)?,
+            knowledge: named(&snapshot.knowledge_ids, &knowledge)?,
+            unresolved: snapshot.unresolved,
+            modules: snapshot.modules,
+            verbose: snapshot.verbose,
+            profile: GameProfile {
+                // only preset names are kept, since the name has to live forever
+                name: GameProfile::preset(&profile.name).map(|preset| preset.name).unwrap_or("custom"),
+                ticks_per_second: profile.ticks_per_second,
+                rounding: profile.rounding,
+                fractional_machines: profile.fractional_machines,
+                buffer_mult: profile.buffer_mult,
+            },
+            efficiency_model: snapshot.efficiency_model,
+            machine_budgets: snapshot.machine_budgets,
+            raw_supply: snapshot.raw_supply.into_iter().collect(),
+            target_efficiency: snapshot.target_efficiency,
+            stream_targets: snapshot.stream_targets,
+            locked_modules: snapshot.locked_modules,
+            check_conservation: snapshot.check_conservation,
+            tick: snapshot.tick,
+            generation,
+        })
+    }
+}
+
+impl Serialize for Factory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Factory {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot = FactorySnapshot::deserialize(deserializer)?;
+        Factory::from_snapshot(snapshot).map_err(|err| de::Error::custom(format!("{err:?}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_dir, read_to_string};
+
+    use crate::lang;
+
+    use super::*;
+
+    /// What `main` runs: the whole base mod, then the example blueprint on top
+    fn example_factory() -> Factory {
+        let mut src = String::new();
+
+        for entry in read_dir("assets/mods/basemod").unwrap() {
+            src += &read_to_string(entry.unwrap().path()).unwrap();
+        }
+
+        let mut factory = Factory::new();
+        factory.add_mod(lang::parse_tokens(lang::tokenize(&src).unwrap()).unwrap()).unwrap();
+        factory.lock_module("base");
+        factory.add_factory(Factory::load_blueprint("assets/factory/main.bp").unwrap()).unwrap();
+        factory
+    }
+
+    #[test]
+    fn example_round_trips_with_the_same_efficiencies() {
+        let factory = example_factory();
+        let json = serde_json::to_string(&factory).unwrap();
+        let restored: Factory = serde_json::from_str(&json).unwrap();
+
+        let mut names: Vec<&String> = factory.streams.keys().collect();
+        let mut restored_names: Vec<&String> = restored.streams.keys().collect();
+        names.sort();
+        restored_names.sort();
+        assert_eq!(names, restored_names);
+
+        for name in names {
+            assert_eq!(factory.streams[name].borrow().efficiency(), restored.streams[name].borrow().efficiency(), "{name}");
+        }
+    }
+}
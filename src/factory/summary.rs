@@ -0,0 +1,175 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{lang::parser::TimeUnit, mult::Mult, rate::Rate, Efficiency, Product};
+
+use super::Factory;
+
+/// Snapshot of a single named stream after solving
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamSummary {
+    pub name: String,
+    pub mult: Mult,
+    pub buildings: usize,
+    pub efficiency: Efficiency,
+    pub outputs: HashMap<Product, Rate>,
+}
+
+/// Snapshot of every named stream plus factory-wide totals
+#[derive(Clone, Debug, PartialEq)]
+pub struct SolutionSummary {
+    /// Sorted by stream name
+    pub streams: Vec<StreamSummary>,
+    pub total_buildings: usize,
+    pub supply: HashMap<Product, Rate>,
+    pub demand: HashMap<Product, Rate>,
+}
+
+/// Net per-tick flow of every product through every named stream, the factory's stoichiometric matrix.
+/// Production is positive and consumption negative
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelMatrix {
+    /// Column headers, ordered by product id
+    pub products: Vec<String>,
+    /// Row headers, sorted by name
+    pub streams: Vec<String>,
+    /// One row per stream, one entry per product
+    pub rows: Vec<Vec<f64>>,
+}
+
+// comma separated, with a header row of product names and a leading column of stream names
+impl Display for ModelMatrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "stream,{}", self.products.join(","))?;
+
+        for (stream, row) in self.streams.iter().zip(&self.rows) {
+            let row: Vec<String> = row.iter().map(f64::to_string).collect();
+            writeln!(f, "{stream},{}", row.join(","))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Factory {
+    pub fn summary(&self) -> SolutionSummary {
+        let mut streams: Vec<StreamSummary> = self.streams.iter().map(|(name, stream)| {
+            let stream = stream.borrow();
+            let outputs = stream.output_rates_map().into_iter()
+                .filter_map(|(product, rate)| rate.map(|rate| (product, rate)))
+                .collect();
+
+            StreamSummary {
+                name: name.to_owned(),
+                mult: stream.mult(),
+                buildings: stream.buildings(),
+                efficiency: self.efficiency_of(&stream),
+                outputs,
+            }
+        }).collect();
+
+        streams.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+
+        SolutionSummary {
+            streams,
+            total_buildings: self.total_buildings(),
+            supply: self.total_supply(),
+            demand: self.total_demand(),
+        }
+    }
+
+    /// Short human-readable report of the factory's current state
+    pub fn summary_text(&self) -> String {
+        let mut out = format!(
+            "{} streams, {} machines ({} buildings)\n",
+            self.streams.len(), self.total_machines(), self.total_buildings(),
+        );
+
+        let power = self.total_power();
+
+        if power > 0.0 {
+            out += &format!("Power: {power}\n");
+        }
+
+        out += "Final outputs:\n";
+
+        for (name, product) in self.final_outputs() {
+            let rate = self.streams[&name].borrow().rate_of(&product).unwrap_or(Rate::ZERO);
+            out += &format!("  {name} -> {} @ {rate}\n", self.product_label(product));
+        }
+
+        let underperforming = self.underperforming();
+
+        if !underperforming.is_empty() {
+            out += "Below target:\n";
+
+            for (name, efficiency) in underperforming {
+                out += &format!("  {name} @ {:.0}%\n", efficiency * 100.0);
+            }
+        }
+
+        out
+    }
+
+    /// Total rate of `product` leaving the factory as a final output, in items per `unit`.
+    /// Only unconsumed outputs count, so intermediate production isn't counted twice
+    pub fn normalized_output(&self, product: &Product, unit: TimeUnit) -> f64 {
+        self.final_outputs().into_iter()
+            .filter(|(_, output)| output == product)
+            .filter_map(|(name, product)| self.streams[&name].borrow().rate_of(&product))
+            // summing nothing gives -0.0
+            .fold(0.0, |total, rate| total + rate.per_unit(unit, self.profile.ticks_per_second))
+    }
+
+    /// Net flow of each product through each named stream at its current mult and efficiency
+    pub fn to_matrix(&self) -> ModelMatrix {
+        let mut names: Vec<&String> = self.streams.keys().collect();
+        names.sort();
+
+        let mut products: Vec<Product> = Vec::new();
+
+        for name in &names {
+            let stream = self.streams[*name].borrow();
+            let recipe = stream.recipe.borrow();
+
+            for part in recipe.inputs.iter().chain(&recipe.outputs) {
+                if !products.contains(&part.product.borrow()) {
+                    products.push(*part.product.borrow());
+                }
+            }
+        }
+
+        products.sort_by_key(|product| product.id);
+
+        let rows = names.iter().map(|name| {
+            let stream = self.streams[*name].borrow();
+            let efficiency = self.efficiency_of(&stream);
+
+            products.iter().map(|product| {
+                let produced = stream.rate_of(product).map(|rate| rate.normalize()).unwrap_or(0.0);
+                let consumed = stream.optimal_inflow_of(product).map(|rate| rate.normalize() * efficiency).unwrap_or(0.0);
+
+                produced - consumed
+            }).collect()
+        }).collect();
+
+        ModelMatrix {
+            products: products.iter().map(|product| self.product_label(*product)).collect(),
+            streams: names.into_iter().cloned().collect(),
+            rows,
+        }
+    }
+
+    /// Summarizes the factory as if each named stream in `overrides` had the given mult, without modifying `self`.
+    /// Names that don't match a stream are ignored
+    pub fn simulate_with_mults(&self, overrides: &HashMap<String, usize>) -> SolutionSummary {
+        let factory = self.deep_clone();
+
+        for (name, &mult) in overrides {
+            if let Some(stream) = factory.streams.get(name) {
+                stream.borrow_mut().set_mult(mult);
+            }
+        }
+
+        factory.summary()
+    }
+}
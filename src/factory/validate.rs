@@ -0,0 +1,248 @@
+use std::{cell::RefCell, collections::HashSet, fmt::Display, rc::Rc, str::FromStr};
+
+use crate::{lang::{parser::Expr, IdentCollector}, Buffer, Efficiency, Product, Recipe};
+
+use super::{builtins, Factory, FactoryError};
+
+/// Buffers holding more than this many cycles of a stream's usage are reported as over-buffered
+pub const OVER_BUFFER_CYCLES: usize = 64;
+
+/// Kinds of validation warnings, referenced by name in `stream.allow(...)`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WarningKind {
+    /// An output of the stream isn't consumed by any other stream
+    UnusedOutput,
+    /// A buffer holds far more than the stream moves per cycle
+    OverBuffered,
+    /// An output buffer can't hold a single cycle's output, so the stream can never finish a cycle
+    UndersizedBuffer,
+    /// The stream runs below full efficiency. Also excludes it from [`Factory::is_balanced`]
+    Unbalanced,
+}
+
+impl WarningKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::UnusedOutput => "unused-output",
+            Self::OverBuffered => "over-buffered",
+            Self::UndersizedBuffer => "undersized-buffer",
+            Self::Unbalanced => "unbalanced",
+        }
+    }
+}
+
+impl Display for WarningKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for WarningKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unused-output" => Ok(Self::UnusedOutput),
+            "over-buffered" => Ok(Self::OverBuffered),
+            "undersized-buffer" => Ok(Self::UndersizedBuffer),
+            "unbalanced" => Ok(Self::Unbalanced),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub stream: String,
+    pub product: Option<Product>,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}: {}", self.kind, self.stream, self.message)
+    }
+}
+
+impl Factory {
+    /// Errors with the first name `ast` uses that nothing defines: not a stream, recipe, product or knowledge in the factory
+    /// or declared by `ast` itself, and not a builtin. Run before evaluating anything, so a typo can't leave a module half loaded
+    pub(super) fn check_defined(&self, ast: &[Expr], module: &str) -> Result<(), FactoryError> {
+        let declared: HashSet<&str> = ast.iter().filter_map(|expr| match expr {
+            Expr::Assign { name, .. } | Expr::Knowledge { name, .. } => Some(name.as_str()),
+            _ => None,
+        }).collect();
+
+        let undefined = IdentCollector::collect(ast).into_iter().find(|name| {
+            !declared.contains(name.as_str())
+                && !self.streams.contains_key(&Self::qualified_stream_name(module, name))
+                && !self.streams.contains_key(name)
+                && self.find_recipe(module, name).is_none()
+                && self.find_product(module, name).is_none()
+                && !self.knowledge.contains_key(name)
+                && builtins::builtin(name).is_none()
+        });
+
+        match undefined {
+            Some(name) => Err(FactoryError::Undefined(name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks named streams for likely mistakes, skipping any warning kinds the stream allows
+    pub fn validate(&self) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+        let mut names: Vec<&String> = self.streams.keys().collect();
+        names.sort();
+
+        for name in names {
+            let stream = self.streams.get(name).unwrap();
+
+            for output in stream.borrow().recipe.borrow().outputs.iter().filter(|output| !output.voided) {
+                let product = *output.product.borrow();
+
+                if self.consumers_of(stream, &product).is_empty() {
+                    let product_name = self.product_label(product);
+
+                    warnings.push(Warning {
+                        kind: WarningKind::UnusedOutput,
+                        stream: name.to_owned(),
+                        product: Some(product),
+                        message: format!("output {product_name} is never consumed"),
+                    });
+                }
+            }
+
+            let stream = stream.borrow();
+            let efficiency = self.efficiency_of(&stream);
+
+            if efficiency < 1.0 {
+                warnings.push(Warning {
+                    kind: WarningKind::Unbalanced,
+                    stream: name.to_owned(),
+                    product: None,
+                    message: format!("running at {:.0}% efficiency", efficiency * 100.0),
+                });
+            }
+
+            let mut buffered: Vec<(&Product, &Buffer)> = stream.buffers.iter().collect();
+            buffered.sort_by_key(|(product, _)| product.id);
+
+            for (product, buffer) in buffered {
+                let cycle_output = stream.recipe.borrow().outputs.iter()
+                    .filter(|output| !output.voided && &*output.product.borrow() == product)
+                    .map(|output| output.amount * stream.machines())
+                    .sum::<usize>();
+
+                if buffer.max < cycle_output {
+                    let product_name = self.product_label(*product);
+
+                    warnings.push(Warning {
+                        kind: WarningKind::UndersizedBuffer,
+                        stream: name.to_owned(),
+                        product: Some(*product),
+                        message: format!("buffer for {product_name} holds {} but one cycle makes {cycle_output}", buffer.max),
+                    });
+                }
+
+                let Some(per_cycle) = stream.per_cycle(product) else {
+                    continue;
+                };
+
+                if buffer.max > per_cycle * OVER_BUFFER_CYCLES {
+                    let product_name = self.product_label(*product);
+                    let recommended = stream.recommended_buffer(product, self.profile.buffer_mult).unwrap();
+
+                    warnings.push(Warning {
+                        kind: WarningKind::OverBuffered,
+                        stream: name.to_owned(),
+                        product: Some(*product),
+                        message: format!("buffer for {product_name} holds {} but only {recommended} is recommended", buffer.max),
+                    });
+                }
+            }
+        }
+
+        warnings.retain(|warning| !self.streams[&warning.stream].borrow().allow.contains(&warning.kind));
+        warnings
+    }
+
+    /// Whether every named stream is within `tolerance` of full efficiency, ignoring streams that allow `unbalanced`
+    pub fn is_balanced(&self, tolerance: f64) -> bool {
+        self.unbalanced(tolerance).is_empty()
+    }
+
+    /// Named streams further than `tolerance` from full efficiency, sorted by name.
+    /// Streams that allow `unbalanced` are skipped
+    pub fn unbalanced(&self, tolerance: f64) -> Vec<(String, Efficiency)> {
+        let mut out: Vec<(String, Efficiency)> = self.streams.iter()
+            .filter(|(_, stream)| !stream.borrow().allow.contains(&WarningKind::Unbalanced))
+            .map(|(name, stream)| (name.to_owned(), self.efficiency_of(&stream.borrow())))
+            .filter(|(_, efficiency)| 1.0 - efficiency > tolerance)
+            .collect();
+
+        out.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+        out
+    }
+
+    /// Defined recipes that no stream runs, sorted by name. A recipe with variants only counts as unused if none of them are run,
+    /// and recipes that are still only declared are left out
+    pub fn unused_recipes(&self) -> Vec<String> {
+        let streams = self.all_streams();
+        let used = |recipe: &Rc<RefCell<Recipe>>| streams.iter().any(|stream| Rc::ptr_eq(&stream.borrow().recipe, recipe));
+
+        let mut names: Vec<String> = self.recipes.iter()
+            .filter(|(name, _)| !self.unresolved.contains(name))
+            .filter(|(name, recipe)| !used(recipe) && !self.recipe_variants.get(*name).is_some_and(|variants| variants.iter().any(used)))
+            .map(|(name, _)| name.to_owned())
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    /// Products that aren't an input or output of any recipe, sorted by name.
+    /// Recipes made up for `source` streams count, since they're only reachable through their stream
+    pub fn unused_products(&self) -> Vec<String> {
+        let variants = self.recipe_variants.values().flatten().cloned();
+        let sources = self.all_streams().into_iter().map(|stream| stream.borrow().recipe.clone());
+        let recipes: Vec<_> = self.recipes.values().cloned().chain(variants).chain(sources).collect();
+
+        let used: HashSet<Product> = recipes.iter()
+            .flat_map(|recipe| {
+                let recipe = recipe.borrow();
+                recipe.inputs.iter().chain(&recipe.outputs).map(|part| *part.product.borrow()).collect::<Vec<_>>()
+            })
+            .collect();
+
+        let mut names: Vec<String> = self.products.iter()
+            .filter(|(name, product)| *name != "__next" && !used.contains(&product.borrow()))
+            .map(|(name, _)| name.to_owned())
+            .collect();
+
+        names.sort();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lang;
+
+    use super::*;
+
+    #[test]
+    fn undefined_names_are_caught_before_anything_runs() {
+        let mut factory = Factory::new();
+        let base = "product iron; recipe Iron() -> iron / 1000ms;";
+        factory.add_mod(lang::parse_tokens(lang::tokenize(base).unwrap()).unwrap()).unwrap();
+
+        let src = "let iron = Iron(); let gear = Gear(iron);";
+        let result = factory.add_factory(lang::parse_tokens(lang::tokenize(src).unwrap()).unwrap());
+
+        assert!(matches!(result, Err(FactoryError::Undefined(name)) if name == "Gear"));
+        assert!(factory.streams.is_empty());
+    }
+}
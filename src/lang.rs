@@ -1,5 +1,42 @@
 pub mod lexer;
 pub mod parser;
+pub mod visit;
 
-pub use lexer::lexer;
-pub use parser::parser;
\ No newline at end of file
+use chumsky::{Parser, Stream};
+pub use lexer::{lexer, LexError, Token};
+pub use parser::{parser, spanned_parser, Expr, ParseError, Span};
+pub use visit::IdentCollector;
+
+/// Lexes `src` into tokens paired with their byte spans
+pub fn tokenize(src: &str) -> Result<Vec<(Token, Span)>, LexError> {
+    lexer()
+        .parse(src)
+        .map(|tokens| tokens.into_iter().map(|(token, span)| (token, span.into())).collect())
+        .map_err(|mut errs| errs.remove(0).into())
+}
+
+/// Parses tokens produced by [`tokenize`] into a list of statements
+pub fn parse_tokens(tokens: Vec<(Token, Span)>) -> Result<Vec<Expr>, ParseError> {
+    let end = tokens.last().map(|(_, span)| span.end).unwrap_or(0);
+    let stream = Stream::from_iter(end..end + 1, tokens.into_iter().map(|(token, span)| (token, span.into())));
+
+    parser()
+        .parse(stream)
+        .map_err(|mut errs| errs.remove(0).into())
+}
+
+/// Like [`parse_tokens`], but keeps the span of source each statement was parsed from
+pub fn parse_spanned(tokens: Vec<(Token, Span)>) -> Result<Vec<(Expr, Span)>, ParseError> {
+    let end = tokens.last().map(|(_, span)| span.end).unwrap_or(0);
+    let stream = Stream::from_iter(end..end + 1, tokens.into_iter().map(|(token, span)| (token, span.into())));
+
+    spanned_parser()
+        .parse(stream)
+        .map_err(|mut errs| errs.remove(0).into())
+}
+
+/// Serializes a parsed program to JSON for tools that don't link against this crate
+#[cfg(feature = "serde")]
+pub fn ast_to_json(ast: &[Expr]) -> Result<String, serde_json::Error> {
+    serde_json::to_string(ast)
+}
@@ -1,14 +1,74 @@
 mod tokens;
 
+use std::{fmt::Display, ops::Range};
+
 use chumsky::{error, Parser};
 pub use tokens::Token;
 
-#[derive(Clone, Copy, Debug)]
-pub enum LexerError {
+use super::parser::Span;
+
+const UNCLOSED_COMMENT: &str = "unclosed block comment";
+const UNCLOSED_STRING: &str = "unclosed string";
+const UNKNOWN_ESCAPE: &str = "unknown escape";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LexError {
+    UnexpectedChar { found: char, span: Span },
+    UnexpectedEof(Span),
+    /// A `/*` with no matching `*/`, spanning from the opening to the end of the file
+    UnclosedComment(Span),
+    /// A string that reaches the end of its line or the file without its closing quote
+    UnclosedString(Span),
+    /// A backslash in a string followed by anything other than `\\`, `"`, `'`, `n`, `t` or `r`
+    UnknownEscape(Span),
+}
+
+impl Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedChar { found, span } => write!(f, "Unexpected character `{found}` at {}..{}", span.start, span.end),
+            Self::UnexpectedEof(span) => write!(f, "Unexpected end of input at {}", span.start),
+            Self::UnclosedComment(span) => write!(f, "Block comment opened at {} is never closed", span.start),
+            Self::UnclosedString(span) => write!(f, "String opened at {} isn't closed before the end of its line, use `\\n` for a newline inside it", span.start),
+            Self::UnknownEscape(span) => write!(f, "Unknown escape sequence at {}..{}", span.start, span.end),
+        }
+    }
+}
+
+impl LexError {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::UnexpectedChar { span, .. } | Self::UnexpectedEof(span) | Self::UnclosedComment(span) | Self::UnclosedString(span) | Self::UnknownEscape(span) => *span,
+        }
+    }
+}
+
+impl From<error::Simple<char>> for LexError {
+    fn from(value: error::Simple<char>) -> Self {
+        let span = value.span().into();
 
+        if let error::SimpleReason::Custom(message) = value.reason() {
+            if message == UNCLOSED_COMMENT {
+                return Self::UnclosedComment(span);
+            }
+
+            if message == UNCLOSED_STRING {
+                return Self::UnclosedString(span);
+            }
+
+            if message == UNKNOWN_ESCAPE {
+                return Self::UnknownEscape(span);
+            }
+        }
+
+        match value.found() {
+            Some(&found) => Self::UnexpectedChar { found, span },
+            None => Self::UnexpectedEof(span),
+        }
+    }
 }
 
-pub fn lexer() -> impl Parser<char, Vec<Token>, Error = error::Simple<char>> {
+pub fn lexer() -> impl Parser<char, Vec<(Token, Range<usize>)>, Error = error::Simple<char>> {
     use chumsky::prelude::*;
 
     // numbers
@@ -29,6 +89,25 @@ pub fn lexer() -> impl Parser<char, Vec<Token>, Error = error::Simple<char>> {
         .map(Token::Float)
         .labelled("float");
 
+    //  seconds, converted to ticks when the factory reads them
+    let seconds = just('-')
+        .or_not()
+        .chain::<char, _, _>(text::int(10))
+        .chain::<char, _, _>(just('.').chain(text::digits(10)).or_not().flatten())
+        .then_ignore(just('s'))
+        .collect::<String>()
+        .map(Token::Seconds)
+        .labelled("seconds");
+
+    //  percentages, only when the `%` directly follows an unsigned number, like `95%` for the ratio 0.95.
+    //  The parser only takes them where it expects a ratio, and `a % b` with a space stays the modulo operator
+    let percent = text::int(10)
+        .chain::<char, _, _>(just('.').chain(text::digits(10)).or_not().flatten())
+        .then_ignore(just('%'))
+        .collect::<String>()
+        .map(Token::Percent)
+        .labelled("percent");
+
     //  integers
     let int = just('-').or_not()
         .chain::<char, _, _>(text::digits(10))
@@ -38,17 +117,38 @@ pub fn lexer() -> impl Parser<char, Vec<Token>, Error = error::Simple<char>> {
         .map(|t| Token::Int(t));
 
     // strings
-    //  the d stands for double quotes
-    let d_string = just('"')
-        .ignore_then(filter(|c| *c != '\\' && *c != '"').repeated())
-        .then_ignore(just('"'))
+    //  either quote can be escaped in either kind of string
+    let escape = just('\\').ignore_then(choice((
+        just('\\'),
+        just('"'),
+        just('\''),
+        just('n').to('\n'),
+        just('t').to('\t'),
+        just('r').to('\r'),
+        any().validate(|c, span, emit| {
+            emit(Simple::custom(span, UNKNOWN_ESCAPE));
+            c
+        }),
+    ))).labelled("escape sequence");
+
+    //  strings stay on one line, so a missing quote errors where the line ends instead of swallowing the rest of the file
+    let quoted = |quote: char| just(quote)
+        .ignore_then(filter(move |c: &char| *c != '\\' && *c != quote && *c != '\n').or(escape).repeated())
+        .then(just(quote).or_not())
+        .validate(|(content, close), span, emit| {
+            if close.is_none() {
+                emit(Simple::custom(span, UNCLOSED_STRING));
+            }
+
+            content
+        })
         .collect::<String>();
 
+    //  the d stands for double quotes
+    let d_string = quoted('"');
+
     //  the s stands for single quotes
-    let s_string = just('\'')
-        .ignore_then(filter(|c| *c != '\\' && *c != '\'').repeated())
-        .then_ignore(just('\''))
-        .collect::<String>();
+    let s_string = quoted('\'');
 
     let string = d_string.or(s_string).map(Token::String).labelled("string");
 
@@ -75,10 +175,16 @@ pub fn lexer() -> impl Parser<char, Vec<Token>, Error = error::Simple<char>> {
         | "let"
         | "blueprint"
         | "producer"
-        | "machine"
         | "recipe"
         | "product"
         | "knowledge"
+        | "void"
+        | "machineBudget"
+        | "targetEfficiency"
+        | "supply"
+        | "include"
+        | "assert"
+        | "source"
         | "food" => Token::Keyword(ident),
         "true" => Token::True,
         "false" => Token::False,
@@ -86,12 +192,46 @@ pub fn lexer() -> impl Parser<char, Vec<Token>, Error = error::Simple<char>> {
     });
 
     // let token = choice((float, int, string, op, ctrl, ident));
-    let token = choice((float, int, string, op, ctrl, ident));
-    let comment = just("//").then(take_until(just('\n'))).padded().ignored();
+    let token = choice((seconds, percent, float, int, string, op, ctrl, ident));
+    let line_comment = just("//").then(take_until(just('\n'))).ignored();
+
+    // block comments nest, and one left open runs into an error at the end of the file instead of eating the rest silently
+    let block_comment = recursive(|block| {
+        just("/*")
+            .ignore_then(block.or(just("*/").not().ignored()).repeated())
+            .then(just("*/").or_not())
+            .validate(|(_, close), span, emit| {
+                if close.is_none() {
+                    emit(Simple::custom(span, UNCLOSED_COMMENT));
+                }
+            })
+    });
+
+    let comment = line_comment.or(block_comment).padded();
 
     token
+        .map_with_span(|token, span| (token, span))
         .padded_by(comment.repeated())
         .padded()
         .repeated()
         .then_ignore(end())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::lang::tokenize;
+
+    use super::*;
+
+    fn tokens(src: &str) -> Vec<Token> {
+        tokenize(src).unwrap().into_iter().map(|(token, _)| token).collect()
+    }
+
+    #[test]
+    fn percent_only_directly_after_a_number() {
+        assert_eq!(tokens("95%"), [Token::Percent("95".to_owned())]);
+        assert_eq!(tokens("12.5%"), [Token::Percent("12.5".to_owned())]);
+        assert_eq!(tokens("a % b"), [Token::Ident("a".to_owned()), Token::InfixOp("%".to_owned()), Token::Ident("b".to_owned())]);
+        // percentages are never negative, so the sign stays with the number
+        assert_eq!(tokens("-5%"), [Token::Int(-5), Token::InfixOp("%".to_owned())]);
+    }
+}
@@ -28,6 +28,10 @@ pub enum Token {
     String(String),
     Int(isize),
     Float(String),
+    /// A number of seconds like `3.5s`
+    Seconds(String),
+    /// A percentage like `95%`, read as the fraction `0.95` wherever the parser expects a ratio and rejected anywhere else
+    Percent(String),
     True,
     False,
 }
@@ -65,6 +69,8 @@ impl From<&Token> for String {
                 Token::String(_) => "String",
                 Token::Int(_) => "Int",
                 Token::Float(_) => "Float",
+                Token::Seconds(_) => "Seconds",
+                Token::Percent(_) => "Percent",
                 Token::True => "True",
                 Token::False => "False",
                 _ => "Epic to_string fail"
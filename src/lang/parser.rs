@@ -1,15 +1,24 @@
-use std::fmt::Display;
+use std::{cmp::Ordering, fmt::Display, ops::Range};
 
-use chumsky::{error::Simple, Parser};
+use chumsky::{error::{Simple, SimpleReason}, Parser};
 
 use super::lexer::Token;
 
+const MISPLACED_PERCENT: &str = "misplaced percentage";
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Span {
     pub start: usize,
     pub end: usize,
 }
 
+impl From<Span> for Range<usize> {
+    fn from(value: Span) -> Self {
+        value.start..value.end
+    }
+}
+
 impl From<logos::Span> for Span {
     fn from(value: logos::Span) -> Self {
         Self {
@@ -21,13 +30,59 @@ impl From<logos::Span> for Span {
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum ParseError {
-    UnexpectedToken{ expected: Token, found: Token, span: Span },
-    UnknownToken(Span),
+    UnexpectedToken{ expected: Vec<Token>, found: Token, span: Span },
+    UnexpectedEof{ expected: Vec<Token>, span: Span },
+    /// A percentage somewhere other than a ratio, like `95% * 3`
+    MisplacedPercent(Span),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let expected = |expected: &Vec<Token>| expected.iter().map(String::from).collect::<Vec<_>>().join(", ");
+
+        match self {
+            Self::UnexpectedToken { expected: e, found, span } => write!(f, "Unexpected {found} at {}..{}, expected one of: {}", span.start, span.end, expected(e)),
+            Self::UnexpectedEof { expected: e, span } => write!(f, "Unexpected end of input at {}, expected one of: {}", span.start, expected(e)),
+            Self::MisplacedPercent(span) => write!(f, "Percentage at {}..{} can only be a ratio, like `targetEfficiency 95%` or `x.efficiency() >= 95%`", span.start, span.end),
+        }
+    }
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::UnexpectedToken { span, .. } | Self::UnexpectedEof { span, .. } | Self::MisplacedPercent(span) => *span,
+        }
+    }
+}
+
+impl From<Simple<Token>> for ParseError {
+    fn from(value: Simple<Token>) -> Self {
+        let span = value.span().into();
+
+        if matches!(value.reason(), SimpleReason::Custom(message) if message == MISPLACED_PERCENT) {
+            return Self::MisplacedPercent(span);
+        }
+
+        // a percentage anywhere but a ratio is just a token nothing expected
+        if let Some(Token::Percent(_)) = value.found() {
+            return Self::MisplacedPercent(span);
+        }
+
+        let expected = value.expected().filter_map(|e| e.clone()).collect();
+
+        match value.found() {
+            Some(found) => Self::UnexpectedToken { expected, found: found.clone(), span },
+            None => Self::UnexpectedEof { expected, span },
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum InfixOp {
     Eq,
+    Ne,
     Gt,
     Gte,
     Lt,
@@ -42,7 +97,8 @@ pub enum InfixOp {
 impl Display for InfixOp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let content = match self {
-            Self::Eq => "=",
+            Self::Eq => "==",
+            Self::Ne => "!=",
             Self::Gt => ">",
             Self::Gte => ">=",
             Self::Lt => "<",
@@ -58,10 +114,32 @@ impl Display for InfixOp {
     }
 }
 
+impl InfixOp {
+    pub fn is_comparison(&self) -> bool {
+        matches!(self, Self::Eq | Self::Ne | Self::Gt | Self::Gte | Self::Lt | Self::Lte)
+    }
+
+    /// Whether operands ordered as `ordering` satisfy this comparison. Unordered operands, like NaN, are only `!=`
+    pub fn compare(&self, ordering: Option<Ordering>) -> bool {
+        match (self, ordering) {
+            (Self::Ne, None) => true,
+            (_, None) => false,
+            (Self::Eq, Some(ordering)) => ordering == Ordering::Equal,
+            (Self::Ne, Some(ordering)) => ordering != Ordering::Equal,
+            (Self::Gt, Some(ordering)) => ordering == Ordering::Greater,
+            (Self::Gte, Some(ordering)) => ordering != Ordering::Less,
+            (Self::Lt, Some(ordering)) => ordering == Ordering::Less,
+            (Self::Lte, Some(ordering)) => ordering != Ordering::Greater,
+            _ => false,
+        }
+    }
+}
+
 impl From<String> for InfixOp {
     fn from(value: String) -> Self {
         match value.as_ref() {
             "==" => Self::Eq,
+            "!=" => Self::Ne,
             ">"  => Self::Gt,
             ">=" => Self::Gte,
             "<"  => Self::Lt,
@@ -76,15 +154,65 @@ impl From<String> for InfixOp {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum TimeUnit {
+    Tick,
+    Millisecond,
+    Second,
+    Minute,
+    Hour,
+}
+
+impl TimeUnit {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "t" | "tick" => Some(Self::Tick),
+            "ms" => Some(Self::Millisecond),
+            "s" | "sec" => Some(Self::Second),
+            "min" => Some(Self::Minute),
+            "h" | "hr" => Some(Self::Hour),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Tick => "t",
+            Self::Millisecond => "ms",
+            Self::Second => "s",
+            Self::Minute => "min",
+            Self::Hour => "h",
+        }
+    }
+
+    /// Length of one of this unit in ticks
+    pub fn to_ticks(self, ticks_per_second: f64) -> f64 {
+        match self {
+            Self::Tick => 1.0,
+            Self::Millisecond => ticks_per_second / 1000.0,
+            Self::Second => ticks_per_second,
+            Self::Minute => ticks_per_second * 60.0,
+            Self::Hour => ticks_per_second * 3600.0,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Literal {
     String(String),
     Int(isize),
     Float(f64),
     Bool(bool),
+    /// <amount>/<unit>
+    Rate(isize, TimeUnit),
+    /// <seconds>s
+    Seconds(f64),
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Expr {
     /// <ident>
     Ident(String),
@@ -98,8 +226,10 @@ pub enum Expr {
     Product { name: String },
     /// <lhs>[<portion>]
     Partial { lhs: Box<Expr>, portion: f64 },
-    /// recipe <name>(<inputs>) -> <outputs> / <period>
-    Recipe { name: String, inputs: Vec<Expr>, outputs: Vec<Expr>, period: Box<Expr> },
+    /// recipe <name>(<inputs>) -> <outputs> / <period> [@power <power>]
+    ///
+    /// The period is in ticks, or cycles per unit of time when it's a rate like `60/min`
+    Recipe { name: String, inputs: Vec<Expr>, outputs: Vec<Expr>, period: Box<Expr>, power: Option<Box<Expr>> },
     // <name>(<args>)
     Call { lhs: Box<Expr>, args: Vec<Expr> },   
     /// <lhs>.<rhs>
@@ -108,25 +238,181 @@ pub enum Expr {
     Knowledge { name: String, outputs: Vec<String> },
     /// [<contents>]
     List { contents: Vec<Expr> },
+    /// void <inner>
+    Void { inner: Box<Expr> },
+    /// machineBudget <machine> <budget>
+    MachineBudget { machine: String, budget: Box<Expr> },
+    /// supply <product> <rate>
+    Supply { product: String, rate: Box<Expr> },
+    /// source <product> @ <rate>
+    Source { product: String, rate: Box<Expr> },
+    /// include "<path>"
+    Include(String),
+    /// <name> = <value>, as an argument to a recipe call
+    KeywordArg { name: String, value: Box<Expr> },
+    /// assert <condition>
+    Assert(Box<Expr>),
+    /// targetEfficiency <ratio>, the efficiency every stream without its own objective should reach
+    TargetEfficiency { ratio: f64 },
+    /// <inner> @ <target>, as the value of a `let`, giving that stream its own target efficiency
+    Objective { inner: Box<Expr>, target: f64 },
+}
+
+impl Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::String(content) => write!(f, "{content:?}"),
+            Self::Int(content) => write!(f, "{content}"),
+            Self::Float(content) => write!(f, "{content:?}"),
+            Self::Bool(content) => write!(f, "{content}"),
+            Self::Rate(amount, unit) => write!(f, "{amount}/{}", unit.name()),
+            Self::Seconds(seconds) => write!(f, "{seconds}s"),
+        }
+    }
+}
+
+/// Joins displayed expressions with commas
+fn comma_separated<T: Display>(items: &[T]) -> String {
+    items.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+// printed back as blueprint source, with parentheses wherever the parser needs them
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ident(name) => write!(f, "{name}"),
+            Self::Literal(literal) => write!(f, "{literal}"),
+            Self::InfixOp { lhs, op, rhs } => {
+                // operators fold left, and comparisons bind looser than arithmetic
+                let lhs = match &**lhs {
+                    Self::InfixOp { op: inner, .. } if inner.is_comparison() && !op.is_comparison() => format!("({lhs})"),
+                    lhs => lhs.to_string(),
+                };
+                let rhs = match &**rhs {
+                    Self::InfixOp { .. } => format!("({rhs})"),
+                    rhs => rhs.to_string(),
+                };
+
+                write!(f, "{lhs} {op} {rhs}")
+            },
+            Self::Assign { name, rhs } => write!(f, "let {name} = {rhs}"),
+            Self::Product { name } => write!(f, "product {name}"),
+            Self::Partial { lhs, portion } => write!(f, "{lhs}[{portion}]"),
+            Self::Recipe { name, inputs, outputs, period, power } => {
+                write!(f, "recipe {name}({}) -> ({}) / {period}", comma_separated(inputs), comma_separated(outputs))?;
+
+                match power {
+                    Some(power) => write!(f, " @power {power}"),
+                    None => Ok(()),
+                }
+            },
+            Self::Call { lhs, args } => write!(f, "{lhs}({})", comma_separated(args)),
+            Self::Access { lhs, rhs } => write!(f, "{lhs}.{rhs}"),
+            Self::Knowledge { name, outputs } => write!(f, "knowledge {name}({})", outputs.join(", ")),
+            Self::List { contents } => write!(f, "[{}]", comma_separated(contents)),
+            Self::Void { inner } => write!(f, "void {inner}"),
+            Self::MachineBudget { machine, budget } => write!(f, "machineBudget {machine} {budget}"),
+            Self::Supply { product, rate } => write!(f, "supply {product} {rate}"),
+            Self::Source { product, rate } => write!(f, "source {product} @ {rate}"),
+            Self::Include(path) => write!(f, "include {path:?}"),
+            Self::KeywordArg { name, value } => write!(f, "{name} = {value}"),
+            Self::Assert(condition) => write!(f, "assert {condition}"),
+            Self::TargetEfficiency { ratio } => write!(f, "targetEfficiency {ratio:?}"),
+            Self::Objective { inner, target } => write!(f, "{inner} @ {target:?}"),
+        }
+    }
+}
+
+/// Takes the recipe period back off of the last output, where `p_iron * 2 / 1000ms` parses as `(p_iron * 2) / 1000`
+fn split_period(mut outputs: Vec<Expr>) -> Option<(Vec<Expr>, Expr)> {
+    let (output, period) = match outputs.pop()? {
+        Expr::InfixOp { lhs, op: InfixOp::Div, rhs } => (*lhs, *rhs),
+        Expr::Void { inner } => match *inner {
+            Expr::InfixOp { lhs, op: InfixOp::Div, rhs } => (Expr::Void { inner: lhs }, *rhs),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    outputs.push(output);
+    Some((outputs, period))
 }
 
 pub fn parser() -> impl Parser<Token, Vec<Expr>, Error = Simple<Token>> {
     use chumsky::prelude::*;
 
+    statement().then_ignore(just(Token::Ctrl(';'))).repeated().at_least(1)
+}
+
+/// Like [`parser`], but pairs each statement with the span it was parsed from, leaving off its `;`
+pub fn spanned_parser() -> impl Parser<Token, Vec<(Expr, Span)>, Error = Simple<Token>> {
+    use chumsky::prelude::*;
+
+    statement()
+        .map_with_span(|statement, span: Range<usize>| (statement, span.into()))
+        .then_ignore(just(Token::Ctrl(';')))
+        .repeated()
+        .at_least(1)
+}
+
+// every `select!` and `try_map` closure returns chumsky's `Simple<Token>` as its error, which clippy finds too big
+#[allow(clippy::result_large_err)]
+fn statement() -> impl Parser<Token, Expr, Error = Simple<Token>> {
+    use chumsky::prelude::*;
+
     let ident = select! { Token::Ident(name) => name }.labelled("ident");
+    // time units are reserved, since `10 / min` has to mean a rate rather than dividing by something named `min`
+    let name = ident.try_map(|name, span| match TimeUnit::from_name(&name) {
+        Some(_) => Err(Simple::custom(span, format!("`{name}` is a time unit, so it can't be used as a name"))),
+        None => Ok(name),
+    });
+    // a ratio like an efficiency, written either as a fraction or a percentage, so `95%` and `0.95` are the same.
+    // Percentages are only read here and against an `.efficiency()`, since `%` anywhere else is the modulo operator
+    let percent = select! { Token::Percent(e) => e.parse::<f64>().unwrap() / 100.0 }.labelled("percentage");
+    let ratio = percent.or(select! { Token::Float(e) => e.parse::<f64>().unwrap() }).labelled("ratio");
     let expr = recursive(|expr| {
         let val = select! {
             Token::Int(e) => Expr::Literal(Literal::Int(e)),
             Token::Float(e) => Expr::Literal(Literal::Float(e.parse::<f64>().unwrap())),
+            Token::Seconds(e) => Expr::Literal(Literal::Seconds(e.parse::<f64>().unwrap())),
             Token::String(e) => Expr::Literal(Literal::String(e)),
             Token::True => Expr::Literal(Literal::Bool(true)),
             Token::False => Expr::Literal(Literal::Bool(false)),
         }.labelled("value");
 
-        let atom = choice((val, ident.map(Expr::Ident), expr.clone().delimited_by(just(Token::Ctrl('(')), just(Token::Ctrl(')')))));
+        let rate = select! { Token::Int(e) => e }
+            .then_ignore(just(Token::InfixOp("/".to_owned())))
+            .then(select! { Token::Ident(unit) if TimeUnit::from_name(&unit).is_some() => TimeUnit::from_name(&unit).unwrap() })
+            // `10 / min(a, b)` divides by the builtin instead
+            .then_ignore(just(Token::Ctrl('(')).not().rewind())
+            .map(|(amount, unit)| Expr::Literal(Literal::Rate(amount, unit)))
+            .labelled("rate");
+
+        // a stream supplied from outside the factory, like `let ore = source iron_ore @ 30/min`
+        let source = just(Token::Keyword("source".to_owned()))
+            .ignore_then(ident)
+            .then_ignore(just(Token::InfixOp("@".to_owned())))
+            .then(rate.clone().or(ident.map(Expr::Ident)))
+            .map(|(product, rate)| Expr::Source { product, rate: Box::new(rate) });
+
+        let atom = choice((source, rate, val, ident.map(Expr::Ident), expr.clone().delimited_by(just(Token::Ctrl('(')), just(Token::Ctrl(')')))));
         let products = expr.clone().separated_by(just(Token::Ctrl(',')));
-        let access = atom.clone().then_ignore(just(Token::InfixOp(".".to_owned()))).then(ident).map(|(lhs, rhs)| { Expr::Access { lhs: Box::new(lhs), rhs }});
-        let call = choice((access.clone(), atom.clone())).then(products.clone().delimited_by(just(Token::Ctrl('(')), just(Token::Ctrl(')')))).map(|(lhs, args)| { Expr::Call { lhs: Box::new(lhs), args } });
+        // keywords are fine as member names, like `stream.recipe`
+        let member = ident.or(select! { Token::Keyword(name) => name });
+        // inputs can be passed by product name, like `Ironwheat(p_wheat = wheat, p_iron_powder = ironPowder)`
+        let keyword_arg = ident
+            .then_ignore(just(Token::InfixOp("=".to_owned())))
+            .then(expr.clone())
+            .map(|(name, value)| Expr::KeywordArg { name, value: Box::new(value) });
+        let args = keyword_arg.or(expr.clone())
+            .separated_by(just(Token::Ctrl(',')))
+            .delimited_by(just(Token::Ctrl('(')), just(Token::Ctrl(')')));
+        // members of nested streams, like `Iron().efficiency()`
+        let atom_call = atom.clone().then(args.clone()).map(|(lhs, args)| Expr::Call { lhs: Box::new(lhs), args });
+        let access = choice((atom_call, atom.clone()))
+            .then(just(Token::InfixOp(".".to_owned())).ignore_then(member).repeated().at_least(1))
+            .foldl(|lhs, rhs| Expr::Access { lhs: Box::new(lhs), rhs });
+        let call = choice((access.clone(), atom.clone())).then(args).map(|(lhs, args)| { Expr::Call { lhs: Box::new(lhs), args } });
         let list = products.clone().delimited_by(just(Token::Ctrl('[')), just(Token::Ctrl(']'))).map(|contents| Expr::List { contents });
         let op_arg = choice((call.clone(), access.clone(), atom.clone(), list.clone()));
 
@@ -134,6 +420,9 @@ pub fn parser() -> impl Parser<Token, Vec<Expr>, Error = Simple<Token>> {
             just(Token::InfixOp("*".to_owned()))
                 .labelled("multiply")
                 .to(InfixOp::Mul),
+            just(Token::InfixOp("/".to_owned()))
+                .labelled("divide")
+                .to(InfixOp::Div),
             just(Token::InfixOp("+".to_owned()))
                 .labelled("add")
                 .to(InfixOp::Add),
@@ -146,39 +435,164 @@ pub fn parser() -> impl Parser<Token, Vec<Expr>, Error = Simple<Token>> {
         )).then(op_arg.clone()).repeated()).foldl(|lhs, (op, rhs)| {
             Expr::InfixOp { lhs: Box::new(lhs), op, rhs: Box::new(rhs) }
         });
+
+        // comparisons bind looser than arithmetic, so `a * 2 > b` compares the product.
+        // The right hand side can be a percentage too, as long as it's compared against an efficiency like `smelter.efficiency() >= 95%`
+        let comparison = infix.clone().then(select! {
+            Token::InfixOp(op) if ["==", "!=", ">", ">=", "<", "<="].contains(&op.as_str()) => InfixOp::from(op),
+        }.labelled("comparison").then(percent.map_with_span(|ratio, span: Range<usize>| (Expr::Literal(Literal::Float(ratio)), Some(span))).or(infix.clone().map(|rhs| (rhs, None)))).repeated())
+            // reported without failing the parse, so it isn't traded for an error from reading just the left hand side
+            .validate(|(lhs, rest), _, emit| rest.into_iter().fold(lhs, |lhs, (op, (rhs, percent_span))| {
+                let is_efficiency = matches!(&lhs, Expr::Call { lhs, args } if args.is_empty() && matches!(&**lhs, Expr::Access { rhs, .. } if rhs == "efficiency"));
+
+                if let Some(span) = percent_span.filter(|_| !is_efficiency) {
+                    emit(Simple::custom(span, MISPLACED_PERCENT));
+                }
+
+                Expr::InfixOp { lhs: Box::new(lhs), op, rhs: Box::new(rhs) }
+            }));
         
-        choice((infix, call, list, access, atom))
+        choice((comparison, call, list, access, atom))
     });
 
     let product = just(Token::Keyword("product".to_owned()))
-        .ignore_then(ident)
+        .ignore_then(name)
         .map(|name| Expr::Product { name: name.to_owned() })
         .boxed();
     
     let products = expr.clone().separated_by(just(Token::Ctrl(',')));
-    let recipe = just(Token::Keyword("recipe".to_owned())).ignore_then(ident)
+    let output = just(Token::Keyword("void".to_owned()))
+        .ignore_then(expr.clone())
+        .map(|inner| Expr::Void { inner: Box::new(inner) })
+        .or(expr.clone());
+    let outputs = output.separated_by(just(Token::Ctrl(',')));
+    let recipe = just(Token::Keyword("recipe".to_owned())).ignore_then(name)
         .then(products.clone().delimited_by(just(Token::Ctrl('(')), just(Token::Ctrl(')'))))
         .then_ignore(just(Token::Output))
-        .then(products.clone().delimited_by(just(Token::Ctrl('(')), just(Token::Ctrl(')'))).or(products.clone()))
-        .then_ignore(just(Token::InfixOp("/".to_owned())))
-        .then(expr.clone())
+        .then(
+            outputs.clone().delimited_by(just(Token::Ctrl('(')), just(Token::Ctrl(')')))
+                .then_ignore(just(Token::InfixOp("/".to_owned())))
+                .then(expr.clone())
+                // without parentheses the period gets read as dividing the last output
+                .or(outputs.try_map(|outputs, span| split_period(outputs).ok_or_else(|| Simple::custom(span, "expected `/ <period>` after recipe outputs"))))
+        )
+        // power drawn by each machine while it runs
+        .then(
+            just(Token::InfixOp("@".to_owned()))
+                .ignore_then(just(Token::Ident("power".to_owned())))
+                .ignore_then(expr.clone())
+                .or_not()
+        )
         .boxed()
-        .map(|(((name, inputs), outputs), period)| {
-            Expr::Recipe { name, inputs, outputs, period: Box::new(period) }
+        .map(|(((name, inputs), (outputs, period)), power)| {
+            Expr::Recipe { name, inputs, outputs, period: Box::new(period), power: power.map(Box::new) }
         });
-    let knowledge = just(Token::Keyword("knowledge".to_owned())).ignore_then(ident.clone())
+    let knowledge = just(Token::Keyword("knowledge".to_owned())).ignore_then(name)
         .then(ident.separated_by(just(Token::Ctrl(','))).allow_trailing().delimited_by(just(Token::Ctrl('(')), just(Token::Ctrl(')'))))
         .map(|(name, outputs)| {
             Expr::Knowledge { name, outputs }
         });
 
+    // `let x = <stream> @ 80%` gives the stream its own target efficiency
     let stream = just(Token::Keyword("let".to_owned()))
-        .ignore_then(ident)
+        .ignore_then(name)
         .then_ignore(just(Token::InfixOp("=".to_owned())))
         .then(expr.clone())
-        .map(|(name, rhs)| {
+        .then(just(Token::InfixOp("@".to_owned())).ignore_then(ratio).or_not())
+        .map(|((name, rhs), target)| {
+            let rhs = match target {
+                Some(target) => Expr::Objective { inner: Box::new(rhs), target },
+                None => rhs,
+            };
+
             Expr::Assign { name, rhs: Box::new(rhs) }
         });
 
-    choice((product, recipe, stream, expr, knowledge)).then_ignore(just(Token::Ctrl(';'))).repeated().at_least(1)
-}
\ No newline at end of file
+    let machine_budget = just(Token::Keyword("machineBudget".to_owned()))
+        .ignore_then(ident)
+        .then(expr.clone())
+        .map(|(machine, budget)| Expr::MachineBudget { machine, budget: Box::new(budget) });
+
+    let supply = just(Token::Keyword("supply".to_owned()))
+        .ignore_then(ident)
+        .then(expr.clone())
+        .map(|(product, rate)| Expr::Supply { product, rate: Box::new(rate) });
+
+    let include = just(Token::Keyword("include".to_owned()))
+        .ignore_then(select! { Token::String(path) => path })
+        .map(Expr::Include);
+
+    let assert = just(Token::Keyword("assert".to_owned()))
+        .ignore_then(expr.clone())
+        .map(|condition| Expr::Assert(Box::new(condition)));
+
+    let target_efficiency = just(Token::Keyword("targetEfficiency".to_owned()))
+        .ignore_then(ratio)
+        .map(|ratio| Expr::TargetEfficiency { ratio });
+
+    choice((product, recipe, stream, machine_budget, supply, include, assert, target_efficiency, expr, knowledge))
+}
+#[cfg(test)]
+mod tests {
+    use crate::lang::{parse_tokens, tokenize};
+
+    use super::*;
+
+    fn parse(src: &str) -> Result<Vec<Expr>, ParseError> {
+        parse_tokens(tokenize(src).unwrap())
+    }
+
+    #[test]
+    fn unit_after_int_is_a_rate() {
+        assert_eq!(parse("10 / min;").unwrap(), [Expr::Literal(Literal::Rate(10, TimeUnit::Minute))]);
+        assert!(matches!(&parse("10 / x;").unwrap()[..], [Expr::InfixOp { op: InfixOp::Div, .. }]));
+    }
+
+    #[test]
+    fn calling_a_unit_named_builtin_divides() {
+        let ast = parse("10 / min(a, b);").unwrap();
+        let [Expr::InfixOp { op: InfixOp::Div, rhs, .. }] = &ast[..] else {
+            panic!("expected a division, got {ast:?}");
+        };
+
+        assert!(matches!(**rhs, Expr::Call { .. }));
+    }
+
+    #[test]
+    fn units_are_reserved_names() {
+        for src in ["let t = Iron();", "product s;", "recipe min() -> iron / 1000ms;", "knowledge h(Iron);"] {
+            assert!(parse(src).is_err(), "{src}");
+        }
+
+        assert!(parse("let tin = Iron();").is_ok());
+    }
+
+    #[test]
+    fn percentages_are_ratios() {
+        assert_eq!(parse("targetEfficiency 95%;").unwrap(), [Expr::TargetEfficiency { ratio: 0.95 }]);
+        assert_eq!(parse("targetEfficiency 0.95;").unwrap(), [Expr::TargetEfficiency { ratio: 0.95 }]);
+
+        let ast = parse("let iron = Iron() @ 80%;").unwrap();
+        let [Expr::Assign { rhs, .. }] = &ast[..] else {
+            panic!("expected a stream, got {ast:?}");
+        };
+        assert!(matches!(&**rhs, Expr::Objective { inner, target: 0.8 } if matches!(**inner, Expr::Call { .. })));
+
+        let ast = parse("assert iron.efficiency() >= 95%;").unwrap();
+        let [Expr::Assert(condition)] = &ast[..] else {
+            panic!("expected an assert, got {ast:?}");
+        };
+        assert!(matches!(&**condition, Expr::InfixOp { op: InfixOp::Gte, rhs, .. } if **rhs == Expr::Literal(Literal::Float(0.95))));
+    }
+
+    #[test]
+    fn percentages_are_rejected_outside_ratios() {
+        for src in ["let x = 95% * 3;", "assert iron.mult() > 95%;"] {
+            assert!(matches!(parse(src), Err(ParseError::MisplacedPercent(_))), "{src}");
+        }
+
+        for src in ["recipe X() -> a / 50%;", "let x = -5%;", "targetEfficiency 95;"] {
+            assert!(parse(src).is_err(), "{src}");
+        }
+    }
+}
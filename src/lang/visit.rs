@@ -0,0 +1,159 @@
+use std::collections::BTreeSet;
+
+use super::parser::{Expr, InfixOp, Literal};
+
+/// Hooks for walking a parsed program without matching on every [`Expr`] variant by hand.
+/// Each method is called by [`Expr::walk`] with the variant's fields, and by default walks into any expressions inside it,
+/// so overriding one and still wanting its children visited means calling `walk` on them yourself
+pub trait Visitor: Sized {
+    fn visit_ident(&mut self, _name: &str) {}
+
+    fn visit_literal(&mut self, _literal: &Literal) {}
+
+    fn visit_infix_op(&mut self, lhs: &Expr, _op: &InfixOp, rhs: &Expr) {
+        lhs.walk(self);
+        rhs.walk(self);
+    }
+
+    fn visit_assign(&mut self, _name: &str, rhs: &Expr) {
+        rhs.walk(self);
+    }
+
+    fn visit_product(&mut self, _name: &str) {}
+
+    fn visit_partial(&mut self, lhs: &Expr, _portion: f64) {
+        lhs.walk(self);
+    }
+
+    fn visit_recipe(&mut self, _name: &str, inputs: &[Expr], outputs: &[Expr], period: &Expr, power: Option<&Expr>) {
+        walk_all(inputs, self);
+        walk_all(outputs, self);
+        period.walk(self);
+
+        if let Some(power) = power {
+            power.walk(self);
+        }
+    }
+
+    fn visit_call(&mut self, lhs: &Expr, args: &[Expr]) {
+        lhs.walk(self);
+        walk_all(args, self);
+    }
+
+    fn visit_access(&mut self, lhs: &Expr, _rhs: &str) {
+        lhs.walk(self);
+    }
+
+    fn visit_knowledge(&mut self, _name: &str, _outputs: &[String]) {}
+
+    fn visit_list(&mut self, contents: &[Expr]) {
+        walk_all(contents, self);
+    }
+
+    fn visit_void(&mut self, inner: &Expr) {
+        inner.walk(self);
+    }
+
+    fn visit_machine_budget(&mut self, _machine: &str, budget: &Expr) {
+        budget.walk(self);
+    }
+
+    fn visit_supply(&mut self, _product: &str, rate: &Expr) {
+        rate.walk(self);
+    }
+
+    fn visit_source(&mut self, _product: &str, rate: &Expr) {
+        rate.walk(self);
+    }
+
+    fn visit_include(&mut self, _path: &str) {}
+
+    fn visit_keyword_arg(&mut self, _name: &str, value: &Expr) {
+        value.walk(self);
+    }
+
+    fn visit_assert(&mut self, condition: &Expr) {
+        condition.walk(self);
+    }
+
+    fn visit_target_efficiency(&mut self, _ratio: f64) {}
+
+    fn visit_objective(&mut self, inner: &Expr, _target: f64) {
+        inner.walk(self);
+    }
+}
+
+/// Walks each of `exprs` in order
+pub fn walk_all(exprs: &[Expr], visitor: &mut impl Visitor) {
+    for expr in exprs {
+        expr.walk(visitor);
+    }
+}
+
+impl Expr {
+    /// Hands this expression's fields to the matching method on `visitor`
+    pub fn walk(&self, visitor: &mut impl Visitor) {
+        match self {
+            Self::Ident(name) => visitor.visit_ident(name),
+            Self::Literal(literal) => visitor.visit_literal(literal),
+            Self::InfixOp { lhs, op, rhs } => visitor.visit_infix_op(lhs, op, rhs),
+            Self::Assign { name, rhs } => visitor.visit_assign(name, rhs),
+            Self::Product { name } => visitor.visit_product(name),
+            Self::Partial { lhs, portion } => visitor.visit_partial(lhs, *portion),
+            Self::Recipe { name, inputs, outputs, period, power } => visitor.visit_recipe(name, inputs, outputs, period, power.as_deref()),
+            Self::Call { lhs, args } => visitor.visit_call(lhs, args),
+            Self::Access { lhs, rhs } => visitor.visit_access(lhs, rhs),
+            Self::Knowledge { name, outputs } => visitor.visit_knowledge(name, outputs),
+            Self::List { contents } => visitor.visit_list(contents),
+            Self::Void { inner } => visitor.visit_void(inner),
+            Self::MachineBudget { machine, budget } => visitor.visit_machine_budget(machine, budget),
+            Self::Supply { product, rate } => visitor.visit_supply(product, rate),
+            Self::Source { product, rate } => visitor.visit_source(product, rate),
+            Self::Include(path) => visitor.visit_include(path),
+            Self::KeywordArg { name, value } => visitor.visit_keyword_arg(name, value),
+            Self::Assert(condition) => visitor.visit_assert(condition),
+            Self::TargetEfficiency { ratio } => visitor.visit_target_efficiency(*ratio),
+            Self::Objective { inner, target } => visitor.visit_objective(inner, *target),
+        }
+    }
+}
+
+/// Every name a program refers to, as opposed to the ones it declares.
+/// Covers plain identifiers along with the products and recipes named by `supply`, `source`, keyword arguments and knowledge
+#[derive(Clone, Debug, Default)]
+pub struct IdentCollector {
+    pub names: BTreeSet<String>,
+}
+
+impl IdentCollector {
+    pub fn collect(ast: &[Expr]) -> BTreeSet<String> {
+        let mut collector = Self::default();
+        walk_all(ast, &mut collector);
+        collector.names
+    }
+}
+
+impl Visitor for IdentCollector {
+    fn visit_ident(&mut self, name: &str) {
+        self.names.insert(name.to_owned());
+    }
+
+    fn visit_knowledge(&mut self, _name: &str, outputs: &[String]) {
+        self.names.extend(outputs.iter().cloned());
+    }
+
+    fn visit_supply(&mut self, product: &str, rate: &Expr) {
+        self.names.insert(product.to_owned());
+        rate.walk(self);
+    }
+
+    fn visit_source(&mut self, product: &str, rate: &Expr) {
+        self.names.insert(product.to_owned());
+        rate.walk(self);
+    }
+
+    fn visit_keyword_arg(&mut self, name: &str, value: &Expr) {
+        self.names.insert(name.to_owned());
+        value.walk(self);
+    }
+}
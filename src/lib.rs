@@ -0,0 +1,731 @@
+use std::{cell::{Cell, RefCell}, cmp::Ordering, collections::{HashMap, HashSet}, fmt::{self, Display}, rc::Rc, str::FromStr};
+
+use factory::Knowledge;
+use lang::parser::Expr;
+
+use crate::{factory::WarningKind, mult::Mult, rate::Rate};
+
+pub mod factory;
+pub mod lang;
+pub mod mult;
+pub mod rate;
+
+
+/// Counter bumped whenever a mult, input, period or recipe changes, which invalidates every efficiency cached against it.
+/// Each factory has its own, shared with all of its streams, so changing one factory never throws away another's caches.
+/// Always compares equal, like [`EfficiencyCache`]
+#[derive(Clone, Debug, Default)]
+pub struct EfficiencyGeneration(Rc<Cell<usize>>);
+
+impl EfficiencyGeneration {
+    fn get(&self) -> usize {
+        self.0.get()
+    }
+
+    pub fn bump(&self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+impl PartialEq for EfficiencyGeneration {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+/// A stream's last computed efficiency and the generation it was computed in.
+/// Always compares equal, so caching never changes whether two streams are equal
+#[derive(Clone, Debug, Default)]
+pub struct EfficiencyCache(Cell<Option<(usize, Efficiency)>>);
+
+impl PartialEq for EfficiencyCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+/// What a buffer does with items that arrive while it's full
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BufferPolicy {
+    /// Items wait where they are, stalling the stream that made them
+    #[default]
+    Block,
+    /// Items that don't fit are thrown away
+    Overflow,
+    /// Every item is thrown away, so the buffer never fills
+    Void,
+}
+
+impl BufferPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Block => "block",
+            Self::Overflow => "overflow",
+            Self::Void => "void",
+        }
+    }
+}
+
+impl Display for BufferPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for BufferPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(Self::Block),
+            "overflow" => Ok(Self::Overflow),
+            "void" => Ok(Self::Void),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Buffer {
+    pub current: usize,
+    /// Floor consumers won't draw below, kept as a safety stock
+    pub min: usize,
+    pub max: usize,
+    /// Pre-stocked input that's drawn down but never refilled from upstream
+    pub reserve: bool,
+    pub policy: BufferPolicy,
+}
+
+impl Display for Buffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.current, self.max)
+    }
+}
+
+impl Buffer {
+    pub const ZERO: Self = Buffer { current: 0, min: 0, max: 0, reserve: false, policy: BufferPolicy::Block };
+
+    pub fn space_left(&self) -> usize {
+        self.max - self.current
+    }
+
+    /// Items that can be taken without going below `min`
+    pub fn available(&self) -> usize {
+        self.current.saturating_sub(self.min)
+    }
+
+    /// Moves what `other` can spare into this buffer, returning how many items were thrown away by this buffer's policy.
+    /// Blocking buffers leave whatever doesn't fit in `other`
+    pub fn fill_from(&mut self, other: &mut Buffer) -> usize {
+        let moved = match self.policy {
+            BufferPolicy::Block => other.available().min(self.space_left()),
+            BufferPolicy::Overflow | BufferPolicy::Void => other.available(),
+        };
+
+        other.current -= moved;
+        self.accept(moved)
+    }
+
+    /// Adds `amount` items regardless of room, returning how many were thrown away by this buffer's policy.
+    /// Blocking buffers keep everything, so callers should check [`Buffer::space_left`] first
+    pub fn accept(&mut self, amount: usize) -> usize {
+        let kept = match self.policy {
+            BufferPolicy::Block => amount,
+            BufferPolicy::Overflow => self.space_left().min(amount),
+            BufferPolicy::Void => 0,
+        };
+
+        self.current += kept;
+        amount - kept
+    }
+
+    pub fn fill_by(&mut self, amount: usize) {
+        self.current += self.space_left().min(amount);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stream {
+    /// Machines running the recipe, which is only fractional when the profile allows part of a machine.
+    /// Rates use it exactly, while anything counting items or buildings goes by [`Stream::machines`].
+    /// [`Mult`] lists every method that rounds it up to whole machines. Set through [`Stream::set_mult`]
+    mult: Mult,
+    pub recipe: Rc<RefCell<Recipe>>,
+    /// Set through [`Stream::set_inputs`]
+    inputs: InputStreams,
+    pub buffers: HashMap<Product, Buffer>,
+    /// How many ticks until next output if currently producing, or None if waiting for inputs
+    pub next: Option<usize>,
+    /// Maximum ticks between outputs
+    pub ticks: usize,
+    /// Fraction of a tick owed by earlier cycles of a fractional period, in units of one over the recipe rate's amount
+    pub carry: usize,
+    /// Validation warnings that shouldn't be reported for this stream
+    pub allow: HashSet<WarningKind>,
+    pub tags: HashSet<String>,
+    /// Made by a `source` statement, standing in for a supply from outside the factory
+    pub source: bool,
+    /// Shared with the factory the stream belongs to and every other stream in it
+    generation: EfficiencyGeneration,
+    efficiency_cache: EfficiencyCache,
+}
+
+impl Stream {
+    pub fn mult(&self) -> Mult {
+        self.mult
+    }
+
+    pub fn inputs(&self) -> &InputStreams {
+        &self.inputs
+    }
+
+    /// Fraction of full speed the stream runs at, limited by its least supplied input.
+    /// Cached until the next mult, input or recipe change in the stream's factory, since each input's rate depends on that
+    /// input's own efficiency
+    pub fn efficiency(&self) -> Efficiency {
+        let generation = self.generation.get();
+
+        if let Some((cached, efficiency)) = self.efficiency_cache.0.get() {
+            if cached == generation {
+                return efficiency;
+            }
+        }
+
+        // a stream that feeds itself gets no supply from the loop while it's still being worked out, instead of recursing forever
+        self.efficiency_cache.0.set(Some((generation, 0.0)));
+
+        let efficiency = self.compute_efficiency();
+        self.efficiency_cache.0.set(Some((generation, efficiency)));
+        efficiency
+    }
+
+    fn compute_efficiency(&self) -> Efficiency {
+        #[cfg(test)]
+        tests::EFFICIENCY_COMPUTATIONS.with(|count| count.set(count.get() + 1));
+
+        if self.inputs.inner.len() == 0 {
+            return 1.0 as Efficiency;
+        }
+
+        self.limiting_input().map_or(0.0, |(_, efficiency)| efficiency)
+    }
+
+    /// The input whose supply ratio sets [`Stream::efficiency`], along with that ratio.
+    /// `None` for streams with no inputs, since nothing upstream can hold them back
+    pub fn limiting_input(&self) -> Option<(Product, Efficiency)> {
+        if self.inputs.inner.is_empty() {
+            return None;
+        }
+
+        self.recipe.borrow().inputs.iter().map(|i| {
+            let product = *i.product.borrow();
+            let rate = self.inputs.rate_of(&product);
+            let optimal_inflow = self.recipe.borrow().optimal_inflow_of(&product).unwrap();
+            // println!("{}: {} / ({} * {}) => {}%", product.id, rate, optimal_inflow, self.mult, (rate / (optimal_inflow * self.mult)) * 100.0);
+            // a stream with no machines demands nothing, so nothing can hold it back
+            (product, (rate / (optimal_inflow * self.mult)).unwrap_or(1.0))
+        }).reduce(|min, next| if next.1 < min.1 { next } else { min })
+            .map(|(product, efficiency)| (product, efficiency.min(1.0)))
+    }
+
+    /// Efficiency of this stream under `model`. Input streams' rates always use [`Stream::efficiency`]
+    pub fn efficiency_with(&self, model: EfficiencyModel) -> Efficiency {
+        match model {
+            EfficiencyModel::WeakestLink => self.efficiency(),
+            EfficiencyModel::Weighted => {
+                if self.inputs.inner.is_empty() {
+                    return 1.0 as Efficiency;
+                }
+
+                let (supplied, demanded) = self.recipe.borrow().inputs.iter().fold((0.0, 0.0), |(supplied, demanded), i| {
+                    let rate = self.inputs.rate_of(&i.product.borrow());
+                    let optimal_inflow = self.recipe.borrow().optimal_inflow_of(&i.product.borrow()).unwrap() * self.mult;
+                    let ratio = (rate / optimal_inflow).unwrap_or(1.0).min(1.0);
+
+                    (supplied + ratio * optimal_inflow.normalize(), demanded + optimal_inflow.normalize())
+                });
+
+                if demanded == 0.0 {
+                    1.0
+                } else {
+                    (supplied / demanded).min(1.0)
+                }
+            },
+        }
+    }
+
+    /// Each input's supplied rate over the rate the recipe wants at the current mult, uncapped.
+    /// Above 1.0 is oversupplied and below is undersupplied, sorted with the furthest from 1.0 first
+    pub fn input_imbalance(&self) -> Vec<(Product, f64)> {
+        let recipe = self.recipe.borrow();
+        let mut ratios: Vec<(Product, f64)> = Vec::with_capacity(recipe.inputs.len());
+
+        for input in &recipe.inputs {
+            let product = *input.product.borrow();
+
+            if ratios.iter().any(|(seen, _)| *seen == product) {
+                continue;
+            }
+
+            let optimal_inflow = recipe.optimal_inflow_of(&product).unwrap() * self.mult;
+            ratios.push((product, (self.inputs.rate_of(&product) / optimal_inflow).unwrap_or(1.0)));
+        }
+
+        ratios.sort_by(|(_, lhs), (_, rhs)| (rhs - 1.0).abs().total_cmp(&(lhs - 1.0).abs()));
+        ratios
+    }
+
+    /// Ticks until the first reserve buffer runs down to its floor at the current mult, counting the cycle in progress.
+    /// Returns `None` if the stream has no reserve buffers
+    pub fn runway(&self) -> Option<usize> {
+        // with no machines nothing is drawn down, so no reserve ever runs out
+        if self.machines() == 0 {
+            return None;
+        }
+
+        let recipe = self.recipe.borrow();
+
+        self.buffers.iter().filter(|(_, buffer)| buffer.reserve).filter_map(|(product, buffer)| {
+            let per_cycle = recipe.required_of(product)? * self.machines();
+            let cycles = buffer.available() / per_cycle;
+
+            Some(cycles * self.ticks + self.next.unwrap_or(0))
+        }).min()
+    }
+
+    pub fn rate_of(&self, product: &Product) -> Option<Rate> {
+        let outflow = self.recipe.borrow().optimal_outflow_of(product)?;
+
+        let eff = self.efficiency();
+        Some(outflow * eff * self.mult)
+    }
+
+    pub fn optimal_inflow_of(&self, product: &Product) -> Option<Rate> {
+        let inflow = self.recipe.borrow().optimal_inflow_of(product)?;
+
+        Some(inflow * self.mult)
+    }
+
+    /// Every output product mapped to its current [`Stream::rate_of`]
+    pub fn output_rates_map(&self) -> HashMap<Product, Option<Rate>> {
+        self.recipe.borrow().outputs.iter().map(|output| {
+            let product = *output.product.borrow();
+            (product, self.rate_of(&product))
+        }).collect()
+    }
+
+    /// Amount of `product` the stream moves per cycle, whether as an input or an output
+    pub fn per_cycle(&self, product: &Product) -> Option<usize> {
+        let recipe = self.recipe.borrow();
+        let amount: usize = recipe.inputs.iter().chain(recipe.outputs.iter())
+            .filter(|part| &*part.product.borrow() == product)
+            .map(|part| part.amount)
+            .sum();
+
+        if amount == 0 {
+            None
+        } else {
+            Some(amount * self.machines())
+        }
+    }
+
+    /// Buffer size the stream would get for `product` when buffers hold `buffer_mult` cycles
+    pub fn recommended_buffer(&self, product: &Product, buffer_mult: usize) -> Option<usize> {
+        Some(self.per_cycle(product)? * buffer_mult)
+    }
+
+    /// Smallest whole mult that makes at least `target` of `product` at full efficiency, or `None` if it isn't an output
+    pub fn mult_for(&self, product: &Product, target: Rate) -> Option<usize> {
+        let per_machine = self.recipe.borrow().optimal_outflow_of(product)?;
+
+        if per_machine.amount == 0 {
+            return None;
+        }
+
+        let needed = target.amount as u128 * per_machine.ticks as u128;
+        let made = per_machine.amount as u128 * target.ticks as u128;
+
+        Some((needed.div_ceil(made) as usize).max(1))
+    }
+
+    /// Sets the multiplier, scaling buffers along with it.
+    /// Sizes are rounded up, so a buffer that held whole cycles at the old mult still does at the new one.
+    /// A stream with no machines has nothing to scale from, so its buffers are made to hold one cycle at the new mult instead
+    pub fn set_mult(&mut self, mult: impl Into<Mult>) {
+        let mult = mult.into();
+        let (old, new) = (self.machines(), mult.ceil());
+        let recipe = self.recipe.borrow();
+
+        for (product, buf) in self.buffers.iter_mut() {
+            buf.max = if old == 0 {
+                recipe.inputs.iter().chain(&recipe.outputs)
+                    .filter(|part| *part.product.borrow() == *product)
+                    .map(|part| part.amount * new)
+                    .sum()
+            } else {
+                (buf.max * new).div_ceil(old)
+            };
+        }
+
+        drop(recipe);
+
+        self.mult = mult;
+
+        if self.recipe.borrow().rate.amount > 0 {
+            self.ticks = self.cycle_rate().period().ceil() as usize;
+        }
+
+        self.generation.bump();
+    }
+
+    /// Whole machines the stream runs, with a fractional mult rounded up.
+    /// Items move a whole cycle of every machine at once, so buffers, ticking and building counts all go by this instead of `mult`
+    pub fn machines(&self) -> usize {
+        self.mult.ceil()
+    }
+
+    /// Cycles per tick of each machine. A fractional mult slows its whole machines down, so together they make exactly
+    /// what `mult` machines at full speed would
+    pub fn cycle_rate(&self) -> Rate {
+        let rate = self.recipe.borrow().rate;
+
+        if self.mult.is_whole() {
+            rate
+        } else {
+            rate * Mult::new(self.mult.numer, self.mult.denom * self.machines())
+        }
+    }
+
+    /// Rewires the streams this one draws from. Every efficiency cached in the factory is thrown away, since the new inputs change this
+    /// stream's efficiency and so everything downstream of it
+    pub fn set_inputs(&mut self, inputs: InputStreams) {
+        self.inputs = inputs;
+        self.generation.bump();
+    }
+
+    /// Moves the stream over to the factory counting `generation`, throwing away its cached efficiency
+    pub fn set_generation(&mut self, generation: EfficiencyGeneration) {
+        self.generation = generation;
+        self.efficiency_cache = EfficiencyCache::default();
+    }
+
+    /// Length of the next cycle in whole ticks. A fractional period like 1.5 carries its remainder over,
+    /// so cycles alternate between 1 and 2 ticks and still average out to the recipe's period
+    pub fn cycle_ticks(&mut self) -> usize {
+        let rate = self.cycle_rate();
+
+        if rate.amount == 0 {
+            return self.ticks.max(1);
+        }
+
+        let owed = rate.ticks + self.carry;
+        self.carry = owed % rate.amount;
+
+        (owed / rate.amount).max(1)
+    }
+
+    /// Number of physical buildings this stream occupies, as opposed to `mult` which counts logical machines
+    pub fn buildings(&self) -> usize {
+        self.machines() * self.recipe.borrow().buildings
+    }
+
+    pub fn until_full(&self, product: &Product) -> Option<usize> {
+        let buffer = self.buffers.get(product)?;
+
+        if buffer.max > 0 {
+            let rate = self.rate_of(product)?;
+
+            // a stalled or empty stream never fills anything
+            if rate.amount == 0 {
+                return None;
+            }
+
+            let packets = (buffer.max - buffer.current) / rate.amount;
+            let ticks = packets * rate.ticks;
+
+            Some(ticks)
+        } else {
+            None
+        }
+    }
+    
+    /// First output without room for another cycle that isn't voided and whose buffer blocks, which stops the stream from starting
+    pub fn blocking_output(&self) -> Option<Product> {
+        self.recipe.borrow().outputs.iter()
+            .filter(|output| !output.voided)
+            .find(|output| {
+                let buffer = self.buffers.get(&*output.product.borrow()).unwrap();
+                buffer.policy == BufferPolicy::Block && output.amount * self.machines() > buffer.space_left()
+            })
+            .map(|output| *output.product.borrow())
+    }
+
+    // before calling this, available products should be moved from output buffers into this stream's input buffers
+    pub fn try_start_produce(&mut self) -> bool {
+        let mut to_satisfy = self.recipe.borrow().inputs.len();
+
+        for input in self.recipe.borrow().inputs.clone() {
+            let buffered = self.buffers.get(&*input.product.borrow()).map(|b| b.available()).unwrap_or(0);
+            
+            if buffered >= self.recipe.borrow().required_of(&input.product.borrow()).unwrap() * self.machines() {
+                to_satisfy -= 1;
+            }
+        }
+
+        if to_satisfy == 0 {
+            if self.blocking_output().is_none() {
+                let machines = self.machines();
+
+                for input in self.recipe.borrow().inputs.clone() {
+                    let buffered = self.buffers.get_mut(&*input.product.borrow()).unwrap();
+                    buffered.current -= input.amount * machines;
+                }
+    
+                // the scheduler may already be counting down a cycle shortened by a fractional period
+                if self.next.is_none() {
+                    self.next = Some(self.ticks);
+                }
+                true
+            } else {
+                false
+            }
+        } else {
+            false
+        }
+    }
+}
+
+/// A source stream with the product it supplies and the most the link between them can carry, if it's capped
+pub type InputLink = (Rc<RefCell<Product>>, Rc<RefCell<Stream>>, Option<Rate>);
+
+#[derive(Clone, PartialEq)]
+pub struct InputStreams {
+    inner: Vec<InputLink>
+}
+
+impl InputStreams {
+    pub const NONE: Self = Self { inner: vec![] };
+}
+
+// input streams are printed by address rather than recursing into them, since shared or looping inputs would never finish
+impl fmt::Debug for InputStreams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.inner.iter().map(|(product, stream, cap)| (*product.borrow(), Rc::as_ptr(stream), cap)))
+            .finish()
+    }
+}
+
+// links made this way aren't capped
+impl From<Vec<(Rc<RefCell<Product>>, Rc<RefCell<Stream>>)>> for InputStreams {
+    fn from(value: Vec<(Rc<RefCell<Product>>, Rc<RefCell<Stream>>)>) -> Self {
+        Self { inner: value.into_iter().map(|(product, stream)| (product, stream, None)).collect() }
+    }
+}
+
+impl From<Vec<InputLink>> for InputStreams {
+    fn from(value: Vec<InputLink>) -> Self {
+        Self { inner: value }
+    }
+}
+
+impl InputStreams {
+    /// Total rate of `product` coming in, with each source held to its link's cap
+    pub fn rate_of(&self, product: &Product) -> Rate {
+        self.inner.iter().filter_map(|(_, stream, cap)| {
+            let rate = stream.borrow().rate_of(product)?;
+            Some(cap.map_or(rate, |cap| rate.min(cap)))
+        }).sum()
+    }
+}
+
+pub type Efficiency = f64;
+
+/// How a stream's per-input supply ratios are combined into one efficiency
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EfficiencyModel {
+    /// The least supplied input limits the whole stream, like a real machine
+    #[default]
+    WeakestLink,
+    /// Average of the per-input ratios weighted by how much of each input the stream demands
+    Weighted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Product {
+    pub id: usize,
+    pub module: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecipePart {
+    pub product: Rc<RefCell<Product>>,
+    pub amount: usize,
+    /// Output that's discarded instead of buffered, so it never blocks production
+    pub voided: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recipe {
+    pub rate: Rate,
+    pub inputs: Vec<RecipePart>,
+    pub outputs: Vec<RecipePart>,
+    pub knowledge: Vec<(Rc<RefCell<Knowledge>>, usize)>,
+    pub unlocked: bool,
+    /// Number of physical buildings one machine running this recipe occupies
+    pub buildings: usize,
+    pub tags: HashSet<String>,
+    /// Machine type this recipe runs in, which may have a budget on the factory
+    pub machine: Option<String>,
+    /// Power one machine draws while running this recipe, in whatever unit the game uses
+    pub power: Option<f64>,
+    pub module: usize,
+}
+
+impl Recipe {
+    pub fn optimal_inflow_of(&self, product: &Product) -> Option<Rate> {
+        let inflow = self.inputs.iter().filter_map(|i| {
+            if &*i.product.borrow() == product {
+                Some(self.rate * i.amount)
+            } else {
+                None
+            }
+        }).fold(Rate::ZERO, |acc, f| acc + f);
+
+        if inflow == Rate::ZERO {
+            None
+        } else {
+            Some(inflow)
+        }
+    }
+
+    pub fn optimal_outflow_of(&self, product: &Product) -> Option<Rate> {
+        let outflow = self.outputs.iter().filter_map(|i| {
+            if &*i.product.borrow() == product {
+                Some(self.rate * i.amount)
+            } else {
+                None
+            }
+        }).fold(Rate::ZERO, |acc, f| acc + f);
+        
+        if outflow == Rate::ZERO {
+            None
+        } else {
+            Some(outflow)
+        }
+    }
+
+    pub fn required_of(&self, product: &Product) -> Option<usize> {
+        let amount = self.inputs.iter().filter_map(|i| {
+            if &*i.product.borrow() == product {
+                Some(i.amount)
+            } else {
+                None
+            }
+        }).sum();
+
+        if amount == 0 {
+            None
+        } else {
+            Some(amount)
+        }
+    }
+
+    /// Total amount of `product` made per cycle, voided or not
+    pub fn produced_of(&self, product: &Product) -> Option<usize> {
+        let amount = self.outputs.iter().filter_map(|o| {
+            if &*o.product.borrow() == product {
+                Some(o.amount)
+            } else {
+                None
+            }
+        }).sum();
+
+        if amount == 0 {
+            None
+        } else {
+            Some(amount)
+        }
+    }
+
+    /// How many `numerator` come out per `denominator`, or None if either isn't an output
+    pub fn output_ratio(&self, numerator: Product, denominator: Product) -> Option<f64> {
+        Some(self.produced_of(&numerator)? as f64 / self.produced_of(&denominator)? as f64)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use crate::factory::{binary_tree, iron_gear_chain, naive_efficiency};
+
+    thread_local! {
+        /// How many times any stream has actually worked out its efficiency instead of using its cache
+        pub static EFFICIENCY_COMPUTATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn computations() -> usize {
+        EFFICIENCY_COMPUTATIONS.with(Cell::get)
+    }
+
+    #[test]
+    fn efficiency_is_cached_until_a_mult_changes() {
+        let factory = iron_gear_chain();
+        let gear = factory.streams["gear"].clone();
+
+        let before = computations();
+        let efficiency = gear.borrow().efficiency();
+        // gear and the iron it draws from
+        assert_eq!(computations() - before, 2);
+
+        let before = computations();
+        assert_eq!(gear.borrow().efficiency(), efficiency);
+        assert_eq!(computations() - before, 0);
+
+        factory.streams["iron"].borrow_mut().set_mult(2);
+        let before = computations();
+        gear.borrow().efficiency();
+        assert_eq!(computations() - before, 2);
+    }
+
+    #[test]
+    fn clones_keep_their_own_caches() {
+        let factory = iron_gear_chain();
+        let clone = factory.deep_clone();
+
+        factory.streams["gear"].borrow().efficiency();
+        clone.streams["gear"].borrow().efficiency();
+
+        clone.streams["iron"].borrow_mut().set_mult(2);
+        let before = computations();
+        factory.streams["gear"].borrow().efficiency();
+        assert_eq!(computations() - before, 0);
+
+        clone.invalidate_efficiencies();
+        factory.streams["gear"].borrow().efficiency();
+        assert_eq!(computations() - before, 0);
+    }
+
+    #[test]
+    fn cached_efficiency_matches_naive() {
+        let mut factory = binary_tree(10);
+
+        for stream in factory.streams.values() {
+            let stream = stream.borrow();
+            assert_eq!(stream.efficiency(), naive_efficiency(&stream));
+        }
+
+        // every cache is warm now, so solving has to throw them away for the new mults to show up
+        let top = factory.streams["l10"].clone();
+        assert!(factory.solve(top) > 0);
+
+        for stream in factory.streams.values() {
+            let stream = stream.borrow();
+            assert_eq!(stream.efficiency(), naive_efficiency(&stream));
+        }
+    }
+}
@@ -1,16 +1,41 @@
-use std::{cell::RefCell, cmp::Ordering, collections::HashMap, fmt::Display, fs::{read_dir, read_to_string}, path::Path, rc::Rc, thread::sleep, time::Duration};
+use std::{fs::{read_dir, read_to_string}, path::Path, thread::sleep, time::Duration};
 
-use chumsky::Parser;
-use factory::Knowledge;
-use lang::parser::Expr;
+use factory::{factory::{Factory, GameProfile, RoundingMode}, lang};
 
-use crate::{factory::Factory, rate::Rate};
-
-mod factory;
-mod lang;
-mod rate;
+mod repl;
 
 fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    // `--profile <name>` plans for one of the game presets, and `--rounding <up|nearest|down>` overrides how it rounds machines
+    let mut profile = GameProfile::default();
+
+    if let Some(name) = take_option(&mut args, "--profile") {
+        let Some(preset) = GameProfile::preset(&name) else {
+            let names: Vec<&str> = GameProfile::PRESETS.iter().map(|preset| preset.name).collect();
+            println!("error: no profile called `{name}`, expected one of: {}", names.join(", "));
+            return;
+        };
+
+        profile = preset;
+    }
+
+    if let Some(mode) = take_option(&mut args, "--rounding") {
+        let Ok(rounding) = mode.parse::<RoundingMode>() else {
+            println!("error: no rounding mode called `{mode}`, expected one of: up, nearest, down");
+            return;
+        };
+
+        profile.rounding = rounding;
+    }
+
+    // `factory ast <blueprint>` only parses, printing the syntax tree instead of running anything
+    #[cfg(feature = "serde")]
+    if args.first().map(String::as_str) == Some("ast") {
+        print_ast(args.get(1).cloned());
+        return;
+    }
+
     let basemod_path = Path::new("assets/mods/basemod");
     let basemod = read_dir(basemod_path).unwrap();
     let mut basemod_src = String::with_capacity(1024);
@@ -25,17 +50,25 @@ fn main() {
         }
     }
 
-    let lex = lang::lexer().parse(basemod_src).unwrap();
-    let ast = lang::parser().parse(lex).unwrap();
-    let mut factory = Factory::new();
+    let lex = lang::tokenize(&basemod_src).unwrap();
+    let ast = lang::parse_tokens(lex).unwrap();
+    let mut factory = Factory::with_profile(profile);
     factory.add_mod(ast).unwrap();
+    factory.lock_module("base");
 
-    let factory_src = read_to_string("assets/factory/main.bp").unwrap();
+    // `factory repl` starts from just the base mod and reads the factory from stdin instead
+    if args.first().map(String::as_str) == Some("repl") {
+        repl::run(factory);
+        return;
+    }
 
-    let lex = lang::lexer().parse(factory_src).unwrap();
-    let ast = lang::parser().parse(lex).unwrap();
+    let ast = Factory::load_blueprint("assets/factory/main.bp").unwrap();
     factory.add_factory(ast).unwrap();
 
+    for warning in factory.validate() {
+        println!("{warning}");
+    }
+
     let dur = Duration::from_millis(250);
 
     loop {
@@ -46,7 +79,7 @@ fn main() {
             println!("{name}: (next in {} ticks)", stream.borrow().next.unwrap_or(99999));
 
             for (product, buffer) in stream.borrow().buffers.iter() {
-                println!("  {}: {buffer}", factory.product_names.get(product).unwrap());
+                println!("  {}: {buffer}", factory.product_label(*product));
             }
         }
 
@@ -56,217 +89,25 @@ fn main() {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Hash, Default)]
-pub struct Buffer {
-    pub current: usize,
-    pub max: usize,
-}
-
-impl Display for Buffer {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}/{}", self.current, self.max)
-    }
-}
-
-impl Buffer {
-    pub const ZERO: Self = Buffer { current: 0, max: 0 };
-
-    pub fn space_left(&self) -> usize {
-        self.max - self.current
-    }
-
-    pub fn fill_from(&mut self, other: &mut Buffer) {
-        if other.current < self.space_left() {
-            self.current += other.current;
-            other.current = 0;
-        } else {
-            other.current -= self.space_left();
-            self.current = self.max;
-        }
-    }
-
-    pub fn fill_by(&mut self, amount: usize) {
-        self.current += self.space_left().min(amount);
-    }
-}
-
-#[derive(Clone, Debug, PartialEq)]
-pub struct Stream {
-    pub mult: usize,
-    pub recipe: Rc<RefCell<Recipe>>,
-    pub inputs: InputStreams,
-    pub buffers: HashMap<Product, Buffer>,
-    /// How many ticks until next output if currently producing, or None if waiting for inputs
-    pub next: Option<usize>,
-    /// Maximum ticks between outputs
-    pub ticks: usize,
-}
-
-impl Stream {
-    pub fn efficiency(&self) -> Efficiency {
-        if self.inputs.inner.len() == 0 {
-            return 1.0 as Efficiency;
-        }
-
-        self.recipe.borrow().inputs.iter().map(|i| {
-            let rate = self.inputs.rate_of(&*i.product.borrow());
-            let optimal_inflow = self.recipe.borrow().optimal_inflow_of(&*i.product.borrow()).unwrap();
-            // println!("{}: {} / ({} * {}) => {}%", i.product.borrow().id, rate, optimal_inflow, self.mult, (rate / (optimal_inflow * self.mult)) * 100.0);
-            rate / (optimal_inflow * self.mult)
-        }).reduce(Efficiency::min).unwrap_or(0.0).min(1.0)
-    }
-
-    pub fn rate_of(&self, product: &Product) -> Option<Rate> {
-        let outflow = self.recipe.borrow().optimal_outflow_of(product)?;
-
-        let eff = self.efficiency();
-        Some(outflow * eff * self.mult)
-    }
-
-    pub fn optimal_inflow_of(&self, product: &Product) -> Option<Rate> {
-        let inflow = self.recipe.borrow().optimal_inflow_of(product)?;
-
-        Some(inflow * self.mult)
-    }
-
-    pub fn until_full(&self, product: &Product) -> Option<usize> {
-        let buffer = self.buffers.get(product)?;
-
-        if buffer.max > 0 {
-            let rate = self.rate_of(product)?;
-            let packets = (buffer.max - buffer.current) / rate.amount;
-            let ticks = packets as f64 * rate.ticks;
-
-            Some(ticks as usize)
-        } else {
-            None
-        }
-    }
-    
-    // before calling this, available products should be moved from output buffers into this stream's input buffers
-    pub fn try_start_produce(&mut self) -> bool {
-        let mut to_satisfy = self.recipe.borrow().inputs.len();
-
-        for input in self.recipe.borrow().inputs.clone() {
-            let buffered = self.buffers.get(&*input.product.borrow()).map(|b| b.current).unwrap_or(0);
-            
-            if buffered >= self.recipe.borrow().required_of(&*input.product.borrow()).unwrap() * self.mult {
-                to_satisfy -= 1;
-            }
-        }
-
-        if to_satisfy == 0 {
-            if self.recipe.borrow().outputs.iter().all(|output| {
-                output.amount * self.mult <= self.buffers.get(&*output.product.borrow()).unwrap().space_left()
-            }) {    
-                for input in self.recipe.borrow().inputs.clone() {
-                    let buffered = self.buffers.get_mut(&*input.product.borrow()).unwrap();
-                    buffered.current -= input.amount * self.mult;
-                }
-    
-                self.next = Some(self.ticks);
-                true
-            } else {
-                false
-            }
-        } else {
-            false
-        }
-    }
-}
-
-#[derive(Clone, Debug, PartialEq)]
-pub struct InputStreams {
-    inner: Vec<(Rc<RefCell<Product>>, Rc<RefCell<Stream>>)>
-}
+/// Removes `flag` and the value after it from `args`, returning the value
+fn take_option(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let idx = args.iter().position(|arg| arg == flag)?;
+    args.remove(idx);
 
-impl InputStreams {
-    pub const NONE: Self = Self { inner: vec![] };
+    Some(if idx < args.len() { args.remove(idx) } else { String::new() })
 }
 
-impl From<Vec<(Rc<RefCell<Product>>, Rc<RefCell<Stream>>)>> for InputStreams {
-    fn from(value: Vec<(Rc<RefCell<Product>>, Rc<RefCell<Stream>>)>) -> Self {
-        Self { inner: value }
-    }
-}
+/// Prints the blueprint at `path`, with everything it includes, as JSON for tools that don't link against this crate
+#[cfg(feature = "serde")]
+fn print_ast(path: Option<String>) {
+    let Some(path) = path else {
+        println!("usage: factory ast <blueprint>");
+        return;
+    };
 
-impl InputStreams {
-    pub fn rate_of(&self, product: &Product) -> Rate {
-        self.inner.iter().filter_map(|s| {
-            s.1.borrow().rate_of(product)
-        }).sum()
+    match Factory::load_blueprint(&path).map(|ast| lang::ast_to_json(&ast)) {
+        Ok(Ok(json)) => println!("{json}"),
+        Ok(Err(err)) => println!("error: {err}"),
+        Err(err) => println!("error: {err}"),
     }
 }
-
-pub type Efficiency = f64;
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub struct Product {
-    pub id: usize,
-    pub module: usize,
-}
-
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct RecipePart {
-    pub product: Rc<RefCell<Product>>,
-    pub amount: usize,
-}
-
-#[derive(Clone, Debug, PartialEq)]
-pub struct Recipe {
-    pub rate: Rate,
-    pub inputs: Vec<RecipePart>,
-    pub outputs: Vec<RecipePart>,
-    pub knowledge: Vec<(Rc<RefCell<Knowledge>>, usize)>,
-    pub unlocked: bool,
-}
-
-impl Recipe {
-    pub fn optimal_inflow_of(&self, product: &Product) -> Option<Rate> {
-        let inflow = self.inputs.iter().filter_map(|i| {
-            if &*i.product.borrow() == product {
-                Some(self.rate * i.amount)
-            } else {
-                None
-            }
-        }).fold(Rate::ZERO, |acc, f| acc + f);
-
-        if inflow == Rate::ZERO {
-            None
-        } else {
-            Some(inflow)
-        }
-    }
-
-    pub fn optimal_outflow_of(&self, product: &Product) -> Option<Rate> {
-        let outflow = self.outputs.iter().filter_map(|i| {
-            if &*i.product.borrow() == product {
-                Some(self.rate * i.amount)
-            } else {
-                None
-            }
-        }).fold(Rate::ZERO, |acc, f| acc + f);
-        
-        if outflow == Rate::ZERO {
-            None
-        } else {
-            Some(outflow)
-        }
-    }
-
-    pub fn required_of(&self, product: &Product) -> Option<usize> {
-        let amount = self.inputs.iter().filter_map(|i| {
-            if &*i.product.borrow() == product {
-                Some(i.amount)
-            } else {
-                None
-            }
-        }).sum();
-
-        if amount == 0 {
-            None
-        } else {
-            Some(amount)
-        }
-    }
-}
\ No newline at end of file
@@ -0,0 +1,109 @@
+use std::{cmp::Ordering, fmt::Display, ops::Mul};
+
+use crate::rate::{approximate, gcd};
+
+/// Exact number of machines a stream runs. Kept as a reduced fraction, so a stream can run part of a machine
+/// when the game allows it, like 3/2 for one machine at full speed and another at half.
+///
+/// Rates use the fraction as it is, but these go by whole machines, with a fractional mult rounded up by [`Mult::ceil`]:
+/// - [`Stream::machines`], and through it item counts per cycle in [`Stream::per_cycle`], [`Stream::recommended_buffer`],
+///   [`Stream::runway`], [`Stream::blocking_output`] and [`Stream::try_start_produce`]
+/// - buffer rescaling in [`Stream::set_mult`] and the building count in [`Stream::buildings`]
+/// - [`Stream::mult_for`], which only ever suggests a whole mult
+/// - [`Factory::apply_machine_budgets`] and [`Factory::total_machines`]
+///
+/// Blueprints can't make a zero mult, since a stream with no machines has nothing to size its buffers by
+///
+/// [`Stream::machines`]: crate::Stream::machines
+/// [`Stream::per_cycle`]: crate::Stream::per_cycle
+/// [`Stream::recommended_buffer`]: crate::Stream::recommended_buffer
+/// [`Stream::runway`]: crate::Stream::runway
+/// [`Stream::blocking_output`]: crate::Stream::blocking_output
+/// [`Stream::try_start_produce`]: crate::Stream::try_start_produce
+/// [`Stream::set_mult`]: crate::Stream::set_mult
+/// [`Stream::buildings`]: crate::Stream::buildings
+/// [`Stream::mult_for`]: crate::Stream::mult_for
+/// [`Factory::apply_machine_budgets`]: crate::factory::Factory::apply_machine_budgets
+/// [`Factory::total_machines`]: crate::factory::Factory::total_machines
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mult {
+    pub numer: usize,
+    /// Never zero
+    pub denom: usize,
+}
+
+impl Mult {
+    pub const ONE: Self = Self { numer: 1, denom: 1 };
+
+    pub fn new(numer: usize, denom: usize) -> Self {
+        assert!(denom != 0);
+
+        if numer == 0 {
+            return Self { numer: 0, denom: 1 };
+        }
+
+        let divisor = gcd(numer as u128, denom as u128) as usize;
+        Self { numer: numer / divisor, denom: denom / divisor }
+    }
+
+    /// Closest fraction to `value`, the same way fractional periods and efficiencies are turned into fractions
+    pub fn from_f64(value: f64) -> Self {
+        let (numer, denom) = approximate(value);
+        Self::new(numer, denom)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.numer as f64 / self.denom as f64
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numer == 0
+    }
+
+    pub fn is_whole(&self) -> bool {
+        self.denom == 1
+    }
+
+    /// Whole machines needed to run this many, since part of a machine still takes a whole one
+    pub fn ceil(&self) -> usize {
+        self.numer.div_ceil(self.denom)
+    }
+}
+
+impl From<usize> for Mult {
+    fn from(value: usize) -> Self {
+        Self { numer: value, denom: 1 }
+    }
+}
+
+impl Mul<Mult> for Mult {
+    type Output = Mult;
+
+    fn mul(self, rhs: Mult) -> Self::Output {
+        Self::new(self.numer * rhs.numer, self.denom * rhs.denom)
+    }
+}
+
+impl PartialOrd for Mult {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Mult {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.numer as u128 * other.denom as u128).cmp(&(other.numer as u128 * self.denom as u128))
+    }
+}
+
+// fractions are shown as decimals, since that's how games show underclocked machines
+impl Display for Mult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_whole() {
+            write!(f, "{}", self.numer)
+        } else {
+            write!(f, "{}", (self.to_f64() * 100.0).round() / 100.0)
+        }
+    }
+}
@@ -1,21 +1,141 @@
-use std::{fmt::Display, iter::Sum, ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign}};
+use std::{cmp::Ordering, fmt::Display, iter::Sum, ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign}};
 
-use crate::Efficiency;
+use crate::{lang::parser::TimeUnit, mult::Mult, Efficiency};
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// Largest denominator used when a float (an efficiency or fractional period) is turned into a fraction
+const MAX_DENOMINATOR: u128 = 1_000_000;
+
+/// Exact fraction of items per ticks. Sums and float scaling keep it reduced, so chained inflows don't drift
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rate {
     /// Number of items per packet
     pub amount: usize,
-    /// Number of ticks per packet
-    pub ticks: f64,
+    /// Number of ticks per packet, never zero
+    pub ticks: usize,
 }
 
 impl Rate {
-    pub const UNIT: Self = Self { amount: 1, ticks: 1.0 };
-    pub const ZERO: Self = Self { amount: 0, ticks: 1.0 };
+    pub const UNIT: Self = Self { amount: 1, ticks: 1 };
+    pub const ZERO: Self = Self { amount: 0, ticks: 1 };
+
+    pub fn new(amount: usize, ticks: usize) -> Self {
+        assert!(ticks != 0);
+        Self { amount, ticks }
+    }
+
+    /// `amount` items every `ticks` ticks, where `ticks` may be fractional
+    pub fn from_f64_ticks(amount: usize, ticks: f64) -> Self {
+        if ticks.fract() == 0.0 {
+            Self::new(amount, ticks as usize)
+        } else {
+            // a period too short to approximate comes back as 0 ticks, so it's clamped to the shortest one we can represent
+            let (numer, denom) = match approximate(ticks) {
+                (0, _) => (1, MAX_DENOMINATOR as usize),
+                fraction => fraction,
+            };
+            Self::reduced(amount as u128 * denom as u128, numer as u128)
+        }
+    }
+
+    /// Rate of `per_tick` items every tick
+    pub fn from_per_tick(per_tick: f64) -> Self {
+        if per_tick <= 0.0 {
+            Self::ZERO
+        } else {
+            let (numer, denom) = approximate(per_tick);
+            Self::reduced(numer as u128, denom as u128)
+        }
+    }
+
+    /// `amount` over `ticks` in lowest terms. Sums of co-prime periods multiply their ticks together, so a fraction
+    /// that still doesn't fit once reduced is approximated instead of wrapping around
+    fn reduced(amount: u128, ticks: u128) -> Self {
+        if amount == 0 {
+            return Self::ZERO;
+        }
+
+        let divisor = gcd(amount, ticks);
+
+        match (usize::try_from(amount / divisor), usize::try_from(ticks / divisor)) {
+            (Ok(amount), Ok(ticks)) => Self { amount, ticks },
+            _ => {
+                let (amount, ticks) = approximate(amount as f64 / ticks as f64);
+                Self { amount, ticks }
+            },
+        }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.amount as f64 / self.ticks as f64
+    }
+
     pub fn normalize(&self) -> f64 {
-        self.amount as f64 / self.ticks
+        self.to_f64()
+    }
+
+    /// Ticks per item, which is the cycle length for a recipe's one-item rate
+    pub fn period(&self) -> f64 {
+        self.ticks as f64 / self.amount as f64
+    }
+
+    pub fn per_second(&self, ticks_per_second: f64) -> f64 {
+        self.to_f64() * ticks_per_second
     }
+
+    pub fn per_minute(&self, ticks_per_second: f64) -> f64 {
+        self.per_second(ticks_per_second) * 60.0
+    }
+
+    /// Items per one `unit` of time
+    pub fn per_unit(&self, unit: TimeUnit, ticks_per_second: f64) -> f64 {
+        self.to_f64() * unit.to_ticks(ticks_per_second)
+    }
+}
+
+pub(crate) fn gcd(mut lhs: u128, mut rhs: u128) -> u128 {
+    while rhs != 0 {
+        (lhs, rhs) = (rhs, lhs % rhs);
+    }
+
+    lhs
+}
+
+/// Closest fraction to `value` with a denominator of at most [`MAX_DENOMINATOR`], as (numerator, denominator)
+pub(crate) fn approximate(value: f64) -> (usize, usize) {
+    if !value.is_finite() || value <= 0.0 {
+        return (0, 1);
+    }
+
+    if value.fract() == 0.0 {
+        return (value as usize, 1);
+    }
+
+    // continued fraction convergents, stopping before the denominator gets too big
+    let (mut prev_numer, mut prev_denom, mut numer, mut denom) = (0u128, 1u128, 1u128, 0u128);
+    let mut rest = value;
+
+    loop {
+        let whole = rest.floor();
+        let next_numer = whole as u128 * numer + prev_numer;
+        let next_denom = whole as u128 * denom + prev_denom;
+
+        if next_denom > MAX_DENOMINATOR {
+            break;
+        }
+
+        (prev_numer, prev_denom, numer, denom) = (numer, denom, next_numer, next_denom);
+
+        let fract = rest - whole;
+
+        if fract < 1e-12 {
+            break;
+        }
+
+        rest = 1.0 / fract;
+    }
+
+    (numer as usize, denom as usize)
 }
 
 impl Mul<usize> for Rate {
@@ -31,14 +151,29 @@ impl Mul<usize> for Rate {
     }
 }
 
+// exact, so a fractional mult doesn't drift the way scaling by a float can
+impl Mul<Mult> for Rate {
+    type Output = Rate;
+
+    fn mul(self, rhs: Mult) -> Self::Output {
+        if rhs.is_whole() {
+            self * rhs.numer
+        } else {
+            Self::reduced(self.amount as u128 * rhs.numer as u128, self.ticks as u128 * rhs.denom as u128)
+        }
+    }
+}
+
 impl Mul<f64> for Rate {
     type Output = Rate;
     
     fn mul(self, efficiency: f64) -> Self::Output {
-        Rate {
-            ticks: self.ticks / efficiency,
-            ..self
+        if efficiency == 1.0 {
+            return self;
         }
+
+        let (numer, denom) = approximate(efficiency);
+        Self::reduced(self.amount as u128 * numer as u128, self.ticks as u128 * denom as u128)
     }
 }
 
@@ -51,7 +186,7 @@ impl MulAssign<usize> for Rate {
 
 impl MulAssign<f64> for Rate {
     fn mul_assign(&mut self, efficiency: f64) {
-        self.ticks /= efficiency;
+        *self = *self * efficiency;
     }
 }
 
@@ -59,16 +194,18 @@ impl Div<f64> for Rate {
     type Output = Rate;
 
     fn div(self, rhs: f64) -> Self::Output {
-        Rate {
-            amount: self.amount,
-            ticks: self.ticks * rhs,
+        if rhs == 1.0 {
+            return self;
         }
+
+        let (numer, denom) = approximate(rhs);
+        Self::reduced(self.amount as u128 * denom as u128, self.ticks as u128 * numer as u128)
     }
 }
 
 impl DivAssign<f64> for Rate {
     fn div_assign(&mut self, rhs: f64) {
-        self.ticks *= rhs;
+        *self = *self / rhs;
     }
 }
 
@@ -87,29 +224,56 @@ impl Add<Rate> for Rate {
                 ..rhs
             }  
         } else {
-            let ticks = (self.normalize()) + (rhs.normalize());
+            let amount = self.amount as u128 * rhs.ticks as u128 + rhs.amount as u128 * self.ticks as u128;
+            Self::reduced(amount, self.ticks as u128 * rhs.ticks as u128)
+        }
+    }
+}
+
+impl AddAssign<Rate> for Rate {
+    fn add_assign(&mut self, rhs: Rate) {
+        *self = *self + rhs;
+    }
+}
+
+// clamped at zero, since a negative rate can't be produced
+impl Sub<Rate> for Rate {
+    type Output = Rate;
 
+    fn sub(self, rhs: Rate) -> Self::Output {
+        if self <= rhs {
+            Self::ZERO
+        } else if self.ticks == rhs.ticks {
             Self {
-                amount: 1,
-                ticks: 1.0 / ticks,
+                amount: self.amount - rhs.amount,
+                ..self
             }
+        } else {
+            let amount = self.amount as u128 * rhs.ticks as u128 - rhs.amount as u128 * self.ticks as u128;
+            Self::reduced(amount, self.ticks as u128 * rhs.ticks as u128)
         }
     }
 }
 
-impl AddAssign<Rate> for Rate {
-    fn add_assign(&mut self, rhs: Rate) {
-        let new = *self + rhs;
-        self.amount = new.amount;
-        self.ticks = new.ticks;
+impl SubAssign<Rate> for Rate {
+    fn sub_assign(&mut self, rhs: Rate) {
+        *self = *self - rhs;
     }
 }
 
+// cross multiplied, so the ratio of two equal rates is exactly 1.0. There's no ratio to a zero rate
 impl Div<Rate> for Rate {
-    type Output = Efficiency;
+    type Output = Option<Efficiency>;
 
     fn div(self, rhs: Rate) -> Self::Output {
-        self.normalize() / rhs.normalize()
+        if rhs.amount == 0 {
+            return None;
+        }
+
+        let lhs = self.amount as u128 * rhs.ticks as u128;
+        let rhs = rhs.amount as u128 * self.ticks as u128;
+
+        Some(lhs as f64 / rhs as f64)
     }
 }
 
@@ -119,15 +283,64 @@ impl Sum<Rate> for Rate {
     }
 }
 
+// equal fractions compare equal even when they aren't reduced the same way
+impl PartialEq for Rate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Rate {}
+
 impl PartialOrd for Rate {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        // some weirdness with fractions
-        self.normalize().partial_cmp(&other.normalize())
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// rates are whole fractions with no NaN, so they're totally ordered by items per tick.
+// This also gives `Rate::min` and `Rate::max`, for folding like `Efficiency::min`
+impl Ord for Rate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let lhs = self.amount as u128 * other.ticks as u128;
+        let rhs = other.amount as u128 * self.ticks as u128;
+
+        lhs.cmp(&rhs)
     }
 }
 
 impl Display for Rate {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}/{:.2}ms", self.amount, self.ticks)
+        write!(f, "{}/{:.2}ms", self.amount, self.ticks as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overflowing_sum_is_approximated() {
+        // two huge co-prime periods whose exact sum needs a denominator past usize::MAX
+        let lhs = Rate::new(1, usize::MAX);
+        let rhs = Rate::new(1, usize::MAX - 1);
+        let sum = lhs + rhs;
+
+        assert!(sum.ticks != 0);
+        assert!((sum.to_f64() - (lhs.to_f64() + rhs.to_f64())).abs() < 1e-12);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn ratio_to_zero_rate_is_none() {
+        assert_eq!(Rate::new(3, 2) / Rate::ZERO, None);
+        assert_eq!(Rate::new(3, 2) / Rate::new(6, 4), Some(1.0));
+    }
+
+    #[test]
+    fn tiny_period_keeps_ticks_nonzero() {
+        let rate = Rate::from_f64_ticks(1, 0.0000001);
+
+        assert!(rate.ticks != 0);
+        assert_eq!(rate, Rate::new(MAX_DENOMINATOR as usize, 1));
+    }
+}
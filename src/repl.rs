@@ -0,0 +1,42 @@
+use std::io::{self, BufRead, Write};
+
+use factory::factory::Factory;
+
+/// Reads blueprint statements from stdin one line at a time, printing the value of each.
+/// Errors are printed and the line can be retried. `:summary`, `:bottleneck` and `:tick <n>` inspect and run the factory
+pub fn run(mut factory: Factory) {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+
+    loop {
+        print!("> ");
+        stdout.flush().unwrap();
+
+        let mut line = String::new();
+
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+
+        let line = line.trim();
+
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [] => {},
+            [":quit"] => break,
+            [":summary"] => print!("{}", factory.summary_text()),
+            [":bottleneck"] => match factory.bottleneck() {
+                Some((name, efficiency)) => println!("Bottleneck: {name} @ {:.0}%", efficiency * 100.0),
+                None => println!("No streams take inputs"),
+            },
+            [":tick", ticks] => match ticks.parse() {
+                Ok(ticks) => factory.tick(ticks),
+                Err(_) => println!("error: `{ticks}` isn't a number of ticks"),
+            },
+            _ => match factory.eval_line(line) {
+                Ok(Some(value)) => println!("{}", factory.describe(&value)),
+                Ok(None) => {},
+                Err(err) => println!("{}", Factory::report_error(line, &err)),
+            },
+        }
+    }
+}